@@ -0,0 +1,3 @@
+ALTER TABLE states
+    DROP COLUMN closes_at,
+    DROP COLUMN closed_message;
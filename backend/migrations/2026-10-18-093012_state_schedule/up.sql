@@ -0,0 +1,3 @@
+ALTER TABLE states
+    ADD COLUMN closes_at TIMESTAMP,
+    ADD COLUMN closed_message TEXT;
@@ -0,0 +1 @@
+ALTER TABLE states ADD COLUMN opens_at TIMESTAMP;
@@ -0,0 +1,13 @@
+CREATE TABLE sessions(
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY NOT NULL,
+    user_id uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMP DEFAULT now() NOT NULL,
+    refreshed_at TIMESTAMP DEFAULT now() NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    revoked_at TIMESTAMP,
+    refresh_token TEXT NOT NULL UNIQUE,
+    previous_refresh_token TEXT
+);
+
+CREATE INDEX sessions_user_id_idx ON sessions(user_id);
+CREATE INDEX sessions_previous_refresh_token_idx ON sessions(previous_refresh_token);
@@ -0,0 +1,12 @@
+ALTER TABLE users
+    ADD COLUMN moderator BOOLEAN DEFAULT false,
+    ADD COLUMN viewer BOOLEAN DEFAULT false;
+
+UPDATE users SET moderator = true
+    WHERE id IN (SELECT user_id FROM user_roles WHERE role = 'moderator');
+UPDATE users SET viewer = true
+    WHERE id IN (SELECT user_id FROM user_roles WHERE role = 'viewer');
+
+DROP TABLE user_roles;
+DROP TABLE role_permissions;
+DROP TABLE roles;
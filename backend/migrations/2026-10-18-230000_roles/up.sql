@@ -0,0 +1,34 @@
+CREATE TABLE roles(
+    name VARCHAR(50) PRIMARY KEY NOT NULL,
+    created_at TIMESTAMP DEFAULT now() NOT NULL
+);
+
+CREATE TABLE role_permissions(
+    role VARCHAR(50) NOT NULL REFERENCES roles(name) ON DELETE CASCADE ON UPDATE CASCADE,
+    permission VARCHAR(50) NOT NULL,
+    PRIMARY KEY(role, permission)
+);
+
+CREATE TABLE user_roles(
+    user_id uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role VARCHAR(50) NOT NULL REFERENCES roles(name) ON DELETE CASCADE ON UPDATE CASCADE,
+    PRIMARY KEY(user_id, role)
+);
+
+CREATE INDEX user_roles_role_idx ON user_roles(role);
+
+-- same access as the old flags had
+INSERT INTO roles(name) VALUES ('moderator'), ('viewer'), ('admin');
+INSERT INTO role_permissions(role, permission) VALUES
+    ('moderator', 'reports:read'),
+    ('moderator', 'reports:resolve'),
+    ('moderator', 'state:toggle'),
+    ('viewer', 'letters:read_secret'),
+    ('admin', 'users:manage');
+
+INSERT INTO user_roles(user_id, role) SELECT id, 'moderator' FROM users WHERE moderator;
+INSERT INTO user_roles(user_id, role) SELECT id, 'viewer' FROM users WHERE viewer;
+
+ALTER TABLE users
+    DROP COLUMN moderator,
+    DROP COLUMN viewer;
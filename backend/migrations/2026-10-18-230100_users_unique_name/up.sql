@@ -0,0 +1,16 @@
+-- usernames were only checked before inserting, which two
+-- requests at the same time could both get through
+DO $$
+DECLARE
+    duplicates TEXT;
+BEGIN
+    SELECT string_agg(quote_literal(name), ', ' ORDER BY name) INTO duplicates
+    FROM (SELECT name FROM users GROUP BY name HAVING count(*) > 1) AS taken;
+
+    IF duplicates IS NOT NULL THEN
+        RAISE EXCEPTION 'usernames % are used by more than one user, rename '
+            'or delete all but one of each before migrating again', duplicates;
+    END IF;
+END $$;
+
+ALTER TABLE users ADD CONSTRAINT users_name_key UNIQUE (name);
@@ -0,0 +1,15 @@
+CREATE TABLE invites(
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY NOT NULL,
+    created_at TIMESTAMP DEFAULT now() NOT NULL,
+    created_by uuid REFERENCES users(id) ON DELETE SET NULL,
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP,
+    used_by uuid REFERENCES users(id) ON DELETE SET NULL,
+    code_hash TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE invite_roles(
+    invite_id uuid NOT NULL REFERENCES invites(id) ON DELETE CASCADE,
+    role VARCHAR(50) NOT NULL REFERENCES roles(name) ON DELETE CASCADE ON UPDATE CASCADE,
+    PRIMARY KEY(invite_id, role)
+);
@@ -0,0 +1,4 @@
+ALTER TABLE roles DROP COLUMN requires_2fa;
+
+DROP TABLE recovery_codes;
+DROP TABLE user_totp;
@@ -0,0 +1,20 @@
+-- only enabled once a code from the authenticator app is confirmed
+CREATE TABLE user_totp(
+    user_id uuid PRIMARY KEY NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMP DEFAULT now() NOT NULL,
+    secret TEXT NOT NULL,
+    enabled_at TIMESTAMP,
+    -- codes can't be used twice
+    last_used_step BIGINT
+);
+
+CREATE TABLE recovery_codes(
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY NOT NULL,
+    user_id uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    used_at TIMESTAMP
+);
+
+CREATE INDEX recovery_codes_user_id_idx ON recovery_codes(user_id);
+
+ALTER TABLE roles ADD COLUMN requires_2fa BOOLEAN NOT NULL DEFAULT false;
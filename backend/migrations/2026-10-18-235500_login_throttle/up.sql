@@ -0,0 +1,21 @@
+-- failed logins of a username (`user:<name>`) or an IP address (`ip:<address>`)
+CREATE TABLE login_failures(
+    key TEXT PRIMARY KEY NOT NULL,
+    failures INTEGER NOT NULL,
+    first_failed_at TIMESTAMP NOT NULL,
+    last_failed_at TIMESTAMP NOT NULL,
+    locked_until TIMESTAMP,
+    -- every lockout lasts twice as long as the previous one
+    lockouts INTEGER NOT NULL DEFAULT 0
+);
+
+-- kept for reviewing brute force attempts later
+CREATE TABLE login_lockouts(
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY NOT NULL,
+    created_at TIMESTAMP DEFAULT now() NOT NULL,
+    key TEXT NOT NULL,
+    -- of the request causing the lockout
+    ip TEXT,
+    failures INTEGER NOT NULL,
+    locked_until TIMESTAMP NOT NULL
+);
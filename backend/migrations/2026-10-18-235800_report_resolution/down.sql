@@ -0,0 +1,11 @@
+DELETE FROM reports WHERE letter_id IS NULL;
+
+ALTER TABLE reports
+    DROP CONSTRAINT reports_letter_id_fkey,
+    ALTER COLUMN letter_id SET NOT NULL,
+    ADD CONSTRAINT reports_letter_id_fkey
+        FOREIGN KEY (letter_id) REFERENCES letters(id);
+
+ALTER TABLE reports
+    DROP COLUMN resolved_by,
+    DROP COLUMN resolved_at;
@@ -0,0 +1,10 @@
+ALTER TABLE reports
+    ADD COLUMN resolved_at TIMESTAMP,
+    ADD COLUMN resolved_by uuid REFERENCES users(id) ON DELETE SET NULL;
+
+-- resolved reports are kept after the letter is deleted
+ALTER TABLE reports
+    ALTER COLUMN letter_id DROP NOT NULL,
+    DROP CONSTRAINT reports_letter_id_fkey,
+    ADD CONSTRAINT reports_letter_id_fkey
+        FOREIGN KEY (letter_id) REFERENCES letters(id) ON DELETE SET NULL;
@@ -0,0 +1,7 @@
+DROP INDEX letters_status_idx;
+
+ALTER TABLE letters
+    DROP COLUMN moderated_at,
+    DROP COLUMN moderated_by,
+    DROP COLUMN moderation_reason,
+    DROP COLUMN status;
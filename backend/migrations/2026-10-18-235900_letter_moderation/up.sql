@@ -0,0 +1,9 @@
+-- letters are never deleted by moderators, so appeals and audits can still see them
+ALTER TABLE letters
+    ADD COLUMN status VARCHAR(20) NOT NULL DEFAULT 'visible'
+        CHECK (status IN ('visible', 'hidden', 'removed', 'pending_review')),
+    ADD COLUMN moderation_reason TEXT,
+    ADD COLUMN moderated_by uuid REFERENCES users(id) ON DELETE SET NULL,
+    ADD COLUMN moderated_at TIMESTAMP;
+
+CREATE INDEX letters_status_idx ON letters(status);
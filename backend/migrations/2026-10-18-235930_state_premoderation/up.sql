@@ -0,0 +1,2 @@
+-- new letters wait in the moderation queue until they're approved
+ALTER TABLE states ADD COLUMN premoderation BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,2 @@
+DELETE FROM role_permissions WHERE permission = 'audit_log:read';
+DROP TABLE audit_log;
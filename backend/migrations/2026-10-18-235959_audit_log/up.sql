@@ -0,0 +1,25 @@
+-- what staff did and to what, there are no foreign keys
+-- since entries have to outlive the rows they're about
+CREATE TABLE audit_log(
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY NOT NULL,
+    created_at TIMESTAMP DEFAULT now() NOT NULL,
+    actor_id uuid,
+    action VARCHAR(50) NOT NULL,
+    letter_id uuid,
+    report_id uuid,
+    user_id uuid,
+    before JSONB,
+    after JSONB
+);
+
+CREATE INDEX audit_log_created_at_idx ON audit_log(created_at);
+CREATE INDEX audit_log_actor_id_idx ON audit_log(actor_id);
+-- for filtering by what the action is about
+CREATE INDEX audit_log_letter_id_idx ON audit_log(letter_id);
+CREATE INDEX audit_log_report_id_idx ON audit_log(report_id);
+CREATE INDEX audit_log_user_id_idx ON audit_log(user_id);
+
+-- it shows what's done to users and roles too, so it's
+-- not given along with the report permissions
+INSERT INTO role_permissions(role, permission)
+    SELECT name, 'audit_log:read' FROM roles WHERE name = 'admin';
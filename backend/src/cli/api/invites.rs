@@ -0,0 +1,159 @@
+use actix_web::web::{self, ServiceConfig};
+use actix_web::{HttpResponse, Responder};
+
+use backend_lib::db::{self, DbPool};
+use backend_lib::models::{
+    invite_expiry, AuditAction, Invite, NewAuditEntry, NewInvite, INVITE_EXPIRY_DURATION,
+    MAX_INVITE_EXPIRY_DURATION,
+};
+use backend_lib::reqs::permission::{Authorized, ManageUsers};
+use backend_lib::resp::error::{self, ApiError};
+use backend_lib::utils::token;
+
+use diesel::Connection;
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{create_test_fn, test_contraints};
+
+#[derive(Debug, Deserialize)]
+pub struct CreateInviteForm {
+    /// Roles given to whoever uses the invite
+    #[serde(default)]
+    roles: Vec<String>,
+    /// How long the invite lasts in seconds
+    expires_in: Option<usize>,
+}
+
+#[actix_web::post("/api/invites")]
+pub async fn create(
+    restrictions: Authorized<ManageUsers>,
+    pool: DbPool,
+    form: web::Json<CreateInviteForm>,
+) -> Result<impl Responder, ApiError> {
+    create_test_fn!(as_number -> test_expires_in, MAX_INVITE_EXPIRY_DURATION as usize, 1);
+
+    let form = form.into_inner();
+    let expires_in = form.expires_in.unwrap_or(INVITE_EXPIRY_DURATION as usize);
+    test_contraints!(
+        test_expires_in,
+        expires_in,
+        "Invite expires too late",
+        "Invite expires too early"
+    );
+
+    let code = token::generate();
+    let code_hash = token::hash(&code);
+    let created_by = restrictions.user_id;
+
+    enum Created {
+        Done(Invite, Vec<String>),
+        UnknownRoles(Vec<String>),
+    }
+
+    let roles = form.roles;
+    let created = db::run(&pool, move |conn| -> anyhow::Result<Created> {
+        let missing = db::roles::find_missing(conn, &roles)?;
+        if !missing.is_empty() {
+            return Ok(Created::UnknownRoles(missing));
+        }
+
+        let now = chrono::Utc::now().naive_utc();
+        conn.transaction(|conn| {
+            let invite = db::invites::insert(
+                conn,
+                NewInvite {
+                    created_by: Some(created_by),
+                    expires_at: invite_expiry(now, expires_in as i64),
+                    code_hash: &code_hash,
+                },
+                &roles,
+            )?;
+
+            let entry =
+                NewAuditEntry::new(Some(created_by), AuditAction::CreateInvite).after(&json!({
+                    "id": invite.id,
+                    "expires_at": invite.expires_at,
+                    "roles": roles,
+                }))?;
+            db::audit_log::insert(conn, entry)?;
+            Ok(Created::Done(invite, roles))
+        })
+    })
+    .await?;
+
+    let (invite, roles) = match created {
+        Created::Done(invite, roles) => (invite, roles),
+        Created::UnknownRoles(missing) => {
+            return Err(error::ErrorBadRequest(format!(
+                "Unknown roles: {}",
+                missing.join(", ")
+            )))
+        }
+    };
+
+    log::info!(
+        "[create] {} created invite {} (roles = {:?})",
+        created_by,
+        invite.id,
+        roles
+    );
+
+    // the code can't be shown again, only its hash is stored
+    Ok(HttpResponse::Created().json(json!({
+        "id": invite.id,
+        "code": code,
+        "expires_at": invite.expires_at,
+        "roles": roles,
+    })))
+}
+
+#[actix_web::get("/api/invites")]
+pub async fn get_unused(
+    _restrictions: Authorized<ManageUsers>,
+    pool: DbPool,
+) -> Result<impl Responder, ApiError> {
+    let invites = db::run(&pool, db::invites::get_unused).await?;
+
+    Ok(HttpResponse::Ok().json(invites))
+}
+
+#[actix_web::delete("/api/invites/{id}")]
+pub async fn delete(
+    restrictions: Authorized<ManageUsers>,
+    pool: DbPool,
+    id: web::Path<Uuid>,
+) -> Result<impl Responder, ApiError> {
+    let id = id.into_inner();
+    let deleted_by = restrictions.user_id;
+    let deleted = db::run(&pool, move |conn| {
+        conn.transaction(|conn| {
+            let info = match db::invites::delete_unused(conn, id)? {
+                Some(n) => n,
+                None => return Ok(false),
+            };
+
+            let entry =
+                NewAuditEntry::new(Some(deleted_by), AuditAction::DeleteInvite).before(&json!({
+                    "id": info.invite.id,
+                    "expires_at": info.invite.expires_at,
+                    "roles": info.roles,
+                }))?;
+            db::audit_log::insert(conn, entry)?;
+            Ok(true)
+        })
+    })
+    .await?;
+
+    if !deleted {
+        return Err(error::ErrorNotFound("Invite not found"));
+    }
+
+    log::info!("[delete] {} deleted invite {}", deleted_by, id);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn apply(cfg: &mut ServiceConfig) {
+    cfg.service(create).service(get_unused).service(delete);
+}
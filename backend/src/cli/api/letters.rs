@@ -3,63 +3,68 @@ use std::sync::Arc;
 use actix_web::web::{self, ServiceConfig};
 use actix_web::{HttpResponse, Responder};
 
-use backend_lib::config::AuthParams;
+use backend_lib::config::{AuthParams, Config};
 use backend_lib::db::{self, DbPool};
-use backend_lib::reqs::user::UserRestrictions;
+use backend_lib::models::{self, AuditAction, LetterStatus, NewAuditEntry};
+use backend_lib::reqs::permission::{Authorized, ReadSecretLetters, ResolveReports};
 use backend_lib::resp::error::{self, ApiError};
 use backend_lib::utils::letter::{decrypt_message, encrypt_message};
 
+use diesel::Connection;
 use serde::Deserialize;
 use serde_json::json;
+use uuid::Uuid;
 
 #[macro_export]
 macro_rules! test_contraints {
     ($fn:ident, $expr:expr, $too_big:expr, $too_small:expr) => {
         if let Some(result) = $fn($expr) {
             return Err(error::ErrorConflict(match result {
-                crate::api::TestResult::TooFew => $too_small,
-                crate::api::TestResult::TooBig => $too_big,
+                $crate::api::TestResult::TooFew => $too_small,
+                $crate::api::TestResult::TooBig => $too_big,
             }));
         }
     };
 }
 
+/// Creates a closure checking the length of a field, limits can be
+/// taken from the configuration since it's not a `fn` item.
 #[macro_export]
 macro_rules! create_test_fn {
     (as_number -> $name:ident, $max:expr, $min:expr) => {
-        fn $name(len: usize) -> Option<crate::api::TestResult> {
+        let $name = |len: usize| -> Option<$crate::api::TestResult> {
             if len > $max {
-                Some(crate::api::TestResult::TooBig)
+                Some($crate::api::TestResult::TooBig)
             } else if len < $min {
-                Some(crate::api::TestResult::TooFew)
+                Some($crate::api::TestResult::TooFew)
             } else {
                 None
             }
-        }
+        };
     };
     ($name:ident, $max:expr, $min:expr) => {
-        fn $name(text: &str) -> Option<crate::api::TestResult> {
+        let $name = |text: &str| -> Option<$crate::api::TestResult> {
             let author_len = text.len();
             if author_len > $max {
-                Some(crate::api::TestResult::TooBig)
+                Some($crate::api::TestResult::TooBig)
             } else if author_len < $min {
-                Some(crate::api::TestResult::TooFew)
+                Some($crate::api::TestResult::TooFew)
             } else {
                 None
             }
-        }
+        };
     };
     ($name:ident, $max:expr) => {
-        fn $name(text: &str) -> Option<crate::api::TestResult> {
+        let $name = |text: &str| -> Option<$crate::api::TestResult> {
             let author_len = text.len();
             if author_len > $max {
-                Some(crate::api::TestResult::TooBig)
+                Some($crate::api::TestResult::TooBig)
             } else if author_len == 0 {
-                Some(crate::api::TestResult::TooFew)
+                Some($crate::api::TestResult::TooFew)
             } else {
                 None
             }
-        }
+        };
     };
 }
 
@@ -67,73 +72,73 @@ macro_rules! create_test_fn {
 pub struct GetLettersQuery {
     pub length: Option<usize>,
     pub offset: Option<usize>,
+    /// Only used by `/api/letters/all`, every status but removed by default
+    pub status: Option<models::LetterStatus>,
 }
 
 static MIN_QUERY_LEN: usize = 1;
-static MAX_QUERY_LEN: usize = 15;
-
-create_test_fn!(as_number -> test_query_len, MAX_QUERY_LEN, MIN_QUERY_LEN);
 
 #[actix_web::get("/api/letters/all")]
 pub async fn get_all(
     auth_params: web::Data<AuthParams>,
-    restrictions: UserRestrictions,
+    config: web::Data<Config>,
+    _restrictions: Authorized<ReadSecretLetters>,
     query: web::Query<GetLettersQuery>,
     pool: DbPool,
 ) -> Result<impl Responder, ApiError> {
-    if restrictions.viewer {
-        let length = query.length.unwrap_or(10);
-        test_contraints!(
-            test_query_len,
-            length,
-            "Query is too big to handle",
-            "Query is too small to handle"
-        );
+    create_test_fn!(as_number -> test_query_len, config.limits.max_query_len, MIN_QUERY_LEN);
 
-        let offset = query.offset.unwrap_or_default();
-        let mut letters = web::block(move || {
-            let mut conn = pool.get()?;
-            db::letters::get_all(&mut conn, length, offset)
-        })
-        .await?
-        .map_err(error::ErrorInternalServerError)?;
-
-        log::info!("[get_all] processing letters");
-        let now = std::time::Instant::now();
-        let secret_letters = letters.iter_mut().filter(|v| v.secret);
-        for letter in secret_letters {
-            log::info!(
-                "[get_all] secret letter found (id = {}); decrypting...",
-                letter.id
-            );
-            let message = decrypt_message(&auth_params.secret_key, &letter.author, &letter.message)
-                .await
-                .map_err(|e| {
-                    log::error!(
-                        "[get_all] failed to decrypt message (id = {:?}): {}",
-                        letter.id,
-                        e
-                    );
-                    error::ErrorInternalServerError(
-                        "There's something wrong to our server, please try again later",
-                    )
-                })?;
-            log::info!("[get_all] done decrypting letter {}", letter.id);
-            letter.message = message;
-        }
+    let length = query.length.unwrap_or(10);
+    test_contraints!(
+        test_query_len,
+        length,
+        "Query is too big to handle",
+        "Query is too small to handle"
+    );
 
-        log::info!("[get_all] processing done ({:.2?})", now.elapsed());
-        Ok(HttpResponse::Ok().json(letters))
-    } else {
-        Err(error::ErrorForbidden("Not authorized to get all letters"))
+    let offset = query.offset.unwrap_or_default();
+    let status = query.status;
+    let mut letters = db::run(&pool, move |conn| {
+        db::letters::get_all(conn, length, offset, status)
+    })
+    .await?;
+
+    log::info!("[get_all] processing letters");
+    let now = std::time::Instant::now();
+    let secret_letters = letters.iter_mut().filter(|v| v.secret);
+    for letter in secret_letters {
+        log::info!(
+            "[get_all] secret letter found (id = {}); decrypting...",
+            letter.id
+        );
+        let message = decrypt_message(&auth_params.secret_keys, &letter.author, &letter.message)
+            .await
+            .map_err(|e| {
+                log::error!(
+                    "[get_all] failed to decrypt message (id = {:?}): {}",
+                    letter.id,
+                    e
+                );
+                error::ErrorInternalServerError(
+                    "There's something wrong to our server, please try again later",
+                )
+            })?;
+        log::info!("[get_all] done decrypting letter {}", letter.id);
+        letter.message = message;
     }
+
+    log::info!("[get_all] processing done ({:.2?})", now.elapsed());
+    Ok(HttpResponse::Ok().json(letters))
 }
 
 #[actix_web::get("/api/letters")]
 pub async fn get_public(
+    config: web::Data<Config>,
     query: web::Query<GetLettersQuery>,
     pool: DbPool,
 ) -> Result<impl Responder, ApiError> {
+    create_test_fn!(as_number -> test_query_len, config.limits.max_query_len, MIN_QUERY_LEN);
+
     let length = query.length.unwrap_or(10);
     test_contraints!(
         test_query_len,
@@ -143,12 +148,10 @@ pub async fn get_public(
     );
 
     let offset = query.offset.unwrap_or_default();
-    let result = web::block(move || {
-        let mut conn = pool.get()?;
-        db::letters::get_all_public(&mut conn, length, offset)
+    let result = db::run(&pool, move |conn| {
+        db::letters::get_all_public(conn, length, offset)
     })
-    .await?
-    .map_err(error::ErrorInternalServerError)?;
+    .await?;
     Ok(HttpResponse::Ok().json(result))
 }
 
@@ -162,6 +165,7 @@ pub struct PostLetterForm {
 #[actix_web::post("/api/letters/post")]
 pub async fn post(
     auth_params: web::Data<AuthParams>,
+    config: web::Data<Config>,
     pool: DbPool,
     form: web::Json<PostLetterForm>,
 ) -> Result<impl Responder, ApiError> {
@@ -169,14 +173,14 @@ pub async fn post(
 
     // check if we still accept submissions
     let pool_1 = pool.clone();
-    let available = web::block(move || {
-        let mut conn = pool.get()?;
-        db::state::is_available(&mut conn)
-    })
-    .await?
-    .map_err(error::ErrorInternalServerError)?;
-
-    if !available {
+    let state = db::run(&pool, db::state::get).await?;
+
+    let now = chrono::Utc::now().naive_utc();
+    if !state
+        .as_ref()
+        .map(|v| v.is_available_at(now))
+        .unwrap_or_default()
+    {
         return Err(error::ErrorUnauthorized(
             "Cannot accept any new submissions",
         ));
@@ -203,13 +207,9 @@ pub async fn post(
         );
     }
 
-    static MIN_MESSAGE_LEN: usize = 50;
-    static MAX_MESSAGE_LEN: usize = 1000;
-
-    static MAX_AUTHOR_LEN: usize = 50;
-
-    create_test_fn!(test_author, MAX_AUTHOR_LEN);
-    create_test_fn!(test_message, MAX_MESSAGE_LEN, MIN_MESSAGE_LEN);
+    let limits = &config.limits;
+    create_test_fn!(test_author, limits.max_author_len);
+    create_test_fn!(test_message, limits.max_message_len, limits.min_message_len);
 
     test_contraints!(
         test_author,
@@ -223,12 +223,10 @@ pub async fn post(
 
     let author = Arc::new(author);
     let author_1 = author.clone();
-    let letter = web::block(move || {
-        let mut conn = pool_1.get()?;
-        db::letters::find_by_author(&mut conn, &*author_1)
+    let letter = db::run(&pool_1, move |conn| {
+        db::letters::find_by_author(conn, &author_1)
     })
-    .await?
-    .map_err(error::ErrorInternalServerError)?;
+    .await?;
 
     if letter.is_some() {
         return Err(error::ErrorConflict(
@@ -256,7 +254,7 @@ pub async fn post(
     // encrypt if possible
     if form.secret {
         log::info!("[post] secret message is enabled; encrypting...");
-        message = encrypt_message(&auth_params.secret_key, &form.author, &message)
+        message = encrypt_message(&auth_params.secret_keys, &author, &message)
             .await
             .map_err(|e| {
                 log::error!("[post] failed to encrypt message: {}", e);
@@ -264,19 +262,79 @@ pub async fn post(
             })?;
     }
 
-    let letter = web::block(move || {
-        let mut conn = pool.get()?;
-        db::letters::insert(&mut conn, &*author, message, form.secret)
+    let status = match state.map(|v| v.premoderation) {
+        Some(true) => LetterStatus::PendingReview,
+        _ => LetterStatus::Visible,
+    };
+
+    let letter = db::run(&pool, move |conn| {
+        db::letters::insert(conn, &*author, message, form.secret, status)
     })
-    .await?
-    .map_err(error::ErrorInternalServerError)?;
+    .await?;
 
     Ok(HttpResponse::Created().json(json!({
         "id": letter.id,
         "created_at": letter.created_at,
+        "status": letter.status,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RestoreLetterForm {
+    reason: Option<String>,
+}
+
+/// Makes a hidden or removed letter visible again, e.g. after an appeal
+#[actix_web::post("/api/letters/{id}/restore")]
+pub async fn restore(
+    restrictions: Authorized<ResolveReports>,
+    pool: DbPool,
+    id: web::Path<Uuid>,
+    form: Option<web::Json<RestoreLetterForm>>,
+) -> Result<impl Responder, ApiError> {
+    let letter_id = id.into_inner();
+    let moderator = restrictions.user_id;
+    let reason = form.and_then(|v| v.into_inner().reason);
+
+    let letter = db::run(&pool, move |conn| {
+        conn.transaction(|conn| {
+            let before = match db::letters::find_any_by_id(conn, &letter_id)? {
+                Some(n) => n,
+                None => return Ok(None),
+            };
+
+            let letter = db::letters::set_status(
+                conn,
+                letter_id,
+                LetterStatus::Visible,
+                reason.as_deref(),
+                moderator,
+                chrono::Utc::now().naive_utc(),
+            )?;
+
+            if let Some(letter) = letter.as_ref() {
+                let mut entry = NewAuditEntry::new(Some(moderator), AuditAction::RestoreLetter)
+                    .before(&before)?
+                    .after(letter)?;
+                entry.letter_id = Some(letter_id);
+                db::audit_log::insert(conn, entry)?;
+            }
+            Ok(letter)
+        })
+    })
+    .await?
+    .ok_or_else(|| error::ErrorNotFound("Letter not found"))?;
+
+    log::info!("[restore] {} restored letter {}", moderator, letter.id);
+    Ok(HttpResponse::Ok().json(json!({
+        "id": letter.id,
+        "status": letter.status,
     })))
 }
 
 pub fn apply(cfg: &mut ServiceConfig) {
-    cfg.service(get_public).service(get_all).service(post);
+    cfg.service(get_public)
+        .service(get_all)
+        .service(post)
+        .service(restore);
 }
@@ -1,13 +1,17 @@
-use actix_web::web::{self, ServiceConfig};
+use actix_web::guard::GuardContext;
+use actix_web::web::ServiceConfig;
 use actix_web::{HttpResponse, Responder};
 
-use backend_lib::db::{self, DbPool};
 use backend_lib::resp::error::{self, ApiError};
 
 use serde_json::json;
 
+pub mod invites;
 pub mod letters;
+pub mod moderation;
 pub mod reports;
+pub mod state;
+pub mod totp;
 pub mod users;
 
 pub enum TestResult {
@@ -22,24 +26,24 @@ pub async fn index() -> Result<impl Responder, ApiError> {
     })))
 }
 
-#[actix_web::get("/api/available")]
-pub async fn is_available(pool: DbPool) -> Result<impl Responder, ApiError> {
-    let result = web::block(move || {
-        let mut conn = pool.get()?;
-        db::state::is_available(&mut conn)
-    })
-    .await?
-    .map_err(error::ErrorInternalServerError)?;
+/// Requests under `/api` are never handled by the frontend, even
+/// if there's no matching route for them.
+pub fn is_api_request(ctx: &GuardContext) -> bool {
+    let path = ctx.head().uri.path();
+    path == "/api" || path.starts_with("/api/")
+}
 
-    Ok(HttpResponse::Ok().json(json!({
-        "available": result,
-    })))
+pub async fn not_found() -> Result<HttpResponse, ApiError> {
+    Err(error::ErrorNotFound("Not found"))
 }
 
 pub fn apply(cfg: &mut ServiceConfig) {
     cfg.configure(letters::apply)
+        .configure(invites::apply)
+        .configure(totp::apply)
         .configure(users::apply)
         .configure(reports::apply)
-        .service(index)
-        .service(is_available);
+        .configure(moderation::apply)
+        .configure(state::apply)
+        .service(index);
 }
@@ -0,0 +1,238 @@
+use actix_web::web::{self, ServiceConfig};
+use actix_web::{HttpResponse, Responder};
+
+use backend_lib::config::{AuthParams, Config};
+use backend_lib::db::audit_log::AuditLogFilter;
+use backend_lib::db::{self, DbPool};
+use backend_lib::models::{AuditAction, LetterStatus, NewAuditEntry, Permission};
+use backend_lib::reqs::permission::{Authorized, ReadAuditLog, ResolveReports, ToggleState};
+use backend_lib::resp::error::{self, ApiError};
+use backend_lib::utils::letter::decrypt_message;
+
+use diesel::Connection;
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{create_test_fn, test_contraints};
+
+static QUEUE_PAGE_SIZE: usize = 10;
+
+#[derive(Debug, Deserialize)]
+pub struct QueueQuery {
+    offset: Option<usize>,
+}
+
+/// Letters waiting to be approved while pre-moderation is enabled, secret
+/// ones stay encrypted unless the moderator can read secret letters too.
+#[actix_web::get("/api/moderation/queue")]
+pub async fn get_queue(
+    auth_params: web::Data<AuthParams>,
+    restrictions: Authorized<ResolveReports>,
+    pool: DbPool,
+    query: web::Query<QueueQuery>,
+) -> Result<impl Responder, ApiError> {
+    let offset = query.offset.unwrap_or_default();
+    let mut letters = db::run(&pool, move |conn| {
+        db::letters::get_all(
+            conn,
+            QUEUE_PAGE_SIZE,
+            offset,
+            Some(LetterStatus::PendingReview),
+        )
+    })
+    .await?;
+
+    if !restrictions.has(Permission::ReadSecretLetters) {
+        return Ok(HttpResponse::Ok().json(letters));
+    }
+
+    for letter in letters.iter_mut().filter(|v| v.secret) {
+        letter.message = decrypt_message(&auth_params.secret_keys, &letter.author, &letter.message)
+            .await
+            .map_err(|e| {
+                log::error!(
+                    "[get_queue] failed to decrypt message (id = {:?}): {}",
+                    letter.id,
+                    e
+                );
+                ApiError::we_pretend_why_it_does_error()
+            })?;
+    }
+
+    Ok(HttpResponse::Ok().json(letters))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReviewForm {
+    reason: Option<String>,
+}
+
+async fn review(
+    pool: DbPool,
+    moderator: Uuid,
+    letter_id: Uuid,
+    approved: bool,
+    form: Option<web::Json<ReviewForm>>,
+) -> Result<HttpResponse, ApiError> {
+    let reason = form.and_then(|v| v.into_inner().reason);
+    let action = match approved {
+        true => AuditAction::ApproveLetter,
+        false => AuditAction::RejectLetter,
+    };
+
+    let letter = db::run(&pool, move |conn| {
+        conn.transaction(|conn| {
+            let before = match db::letters::find_any_by_id(conn, &letter_id)? {
+                Some(n) => n,
+                None => return Ok(None),
+            };
+
+            let letter = db::letters::review(
+                conn,
+                letter_id,
+                approved,
+                reason.as_deref(),
+                moderator,
+                chrono::Utc::now().naive_utc(),
+            )?;
+
+            if let Some(letter) = letter.as_ref() {
+                let mut entry = NewAuditEntry::new(Some(moderator), action)
+                    .before(&before)?
+                    .after(letter)?;
+                entry.letter_id = Some(letter_id);
+                db::audit_log::insert(conn, entry)?;
+            }
+            Ok(letter)
+        })
+    })
+    .await?
+    .ok_or_else(|| error::ErrorNotFound("Letter is not waiting for review"))?;
+
+    log::info!(
+        "[review] {} {} letter {}",
+        moderator,
+        if approved { "approved" } else { "rejected" },
+        letter.id
+    );
+    Ok(HttpResponse::Ok().json(json!({
+        "id": letter.id,
+        "status": letter.status,
+    })))
+}
+
+#[actix_web::post("/api/moderation/letters/{id}/approve")]
+pub async fn approve(
+    restrictions: Authorized<ResolveReports>,
+    pool: DbPool,
+    id: web::Path<Uuid>,
+    form: Option<web::Json<ReviewForm>>,
+) -> Result<impl Responder, ApiError> {
+    review(pool, restrictions.user_id, id.into_inner(), true, form).await
+}
+
+#[actix_web::post("/api/moderation/letters/{id}/reject")]
+pub async fn reject(
+    restrictions: Authorized<ResolveReports>,
+    pool: DbPool,
+    id: web::Path<Uuid>,
+    form: Option<web::Json<ReviewForm>>,
+) -> Result<impl Responder, ApiError> {
+    review(pool, restrictions.user_id, id.into_inner(), false, form).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PremoderationForm {
+    enabled: bool,
+}
+
+/// Letters posted while it's enabled stay in the queue even after
+/// it's disabled again, they still have to be reviewed.
+#[actix_web::put("/api/moderation/premoderation")]
+pub async fn set_premoderation(
+    restrictions: Authorized<ToggleState>,
+    pool: DbPool,
+    form: web::Json<PremoderationForm>,
+) -> Result<impl Responder, ApiError> {
+    let enabled = form.enabled;
+    let actor = restrictions.user_id;
+    let state = db::run(&pool, move |conn| {
+        conn.transaction(|conn| {
+            let before = db::state::get(conn)?;
+            let state = db::state::set_premoderation(conn, enabled)?;
+
+            let entry = NewAuditEntry::new(Some(actor), AuditAction::SetPremoderation)
+                .before(&before)?
+                .after(&state)?;
+            db::audit_log::insert(conn, entry)?;
+            Ok(state)
+        })
+    })
+    .await?;
+
+    log::info!(
+        "[set_premoderation] pre-moderation is now {}",
+        if state.premoderation {
+            "enabled"
+        } else {
+            "disabled"
+        }
+    );
+    Ok(HttpResponse::Ok().json(json!({
+        "premoderation": state.premoderation,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditLogQuery {
+    length: Option<usize>,
+    offset: Option<usize>,
+    /// Only actions done by this user
+    actor: Option<Uuid>,
+    action: Option<AuditAction>,
+    /// Only actions about this letter, report or user
+    target: Option<Uuid>,
+}
+
+static MIN_QUERY_LEN: usize = 1;
+
+/// Newest entries first
+#[actix_web::get("/api/moderation/audit-log")]
+pub async fn get_audit_log(
+    config: web::Data<Config>,
+    _restrictions: Authorized<ReadAuditLog>,
+    pool: DbPool,
+    query: web::Query<AuditLogQuery>,
+) -> Result<impl Responder, ApiError> {
+    create_test_fn!(as_number -> test_query_len, config.limits.max_query_len, MIN_QUERY_LEN);
+
+    let length = query.length.unwrap_or(10);
+    test_contraints!(
+        test_query_len,
+        length,
+        "Query length is too big to handle",
+        "Query length is too small to handle"
+    );
+
+    let offset = query.offset.unwrap_or_default();
+    let filter = AuditLogFilter {
+        actor_id: query.actor,
+        action: query.action,
+        target_id: query.target,
+    };
+    let entries = db::run(&pool, move |conn| {
+        db::audit_log::get_all(conn, &filter, length, offset)
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+pub fn apply(cfg: &mut ServiceConfig) {
+    cfg.service(get_queue)
+        .service(approve)
+        .service(reject)
+        .service(set_premoderation)
+        .service(get_audit_log);
+}
@@ -3,14 +3,15 @@ use std::sync::Arc;
 use actix_web::web::ServiceConfig;
 use actix_web::{web, HttpResponse, Responder};
 
-use backend_lib::config::AuthParams;
+use backend_lib::config::{AuthParams, Config};
 use backend_lib::db::{self, DbPool};
-use backend_lib::models;
+use backend_lib::models::{self, AuditAction, NewAuditEntry, Permission};
 
-use backend_lib::reqs::user::UserRestrictions;
+use backend_lib::reqs::permission::{Authorized, ReadReports, ResolveReports};
 use backend_lib::resp::error::{self, ApiError};
 
 use backend_lib::utils::letter::decrypt_message;
+use diesel::Connection;
 use serde::Deserialize;
 use serde_json::json;
 use uuid::Uuid;
@@ -22,140 +23,160 @@ pub struct RetrieveLetterQuery {
     offset: Option<usize>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ResolveReportQuery {
+    /// Hides the letter instead of removing it
+    #[serde(default)]
+    hide: bool,
+    reason: Option<String>,
+}
+
 #[actix_web::delete("/api/reports/resolve/{id}")]
 pub async fn resolve(
-    restrictions: UserRestrictions,
+    restrictions: Authorized<ResolveReports>,
     pool: DbPool,
     id: web::Path<Uuid>,
+    query: web::Query<ResolveReportQuery>,
 ) -> Result<impl Responder, ApiError> {
-    if !restrictions.moderator {
-        Err(error::ErrorForbidden("Not authorized to revoke reports"))
-    } else {
-        let pool = Arc::new(pool);
-        let pool_1 = pool.clone();
-
-        let id_1 = id.clone();
-
-        let report = web::block(move || {
-            let mut conn = pool.get()?;
-            db::reports::get_pending(&mut conn, id_1)
-        })
-        .await?
-        .map_err(error::ErrorInternalServerError)?;
-
-        let report = match report {
-            Some(n) => n,
-            None => return Err(error::ErrorNotFound("Report not found")),
-        };
-
-        // delete the report first before the letter
-        // otherwise we can get an error from Postgres
-        let pool = pool_1.clone();
-        web::block(move || {
-            let mut conn = pool_1.get()?;
-            db::reports::delete(&mut conn, id.into_inner())
-        })
-        .await?
-        .map_err(error::ErrorInternalServerError)?;
-
-        web::block(move || {
-            let mut conn = pool.get()?;
-            db::letters::delete(&mut conn, report.id)
+    let report_id = id.into_inner();
+    let resolver = restrictions.user_id;
+    let query = query.into_inner();
+    let letter_status = match query.hide {
+        true => models::LetterStatus::Hidden,
+        false => models::LetterStatus::Removed,
+    };
+
+    let resolved = db::run(&pool, move |conn| {
+        conn.transaction(|conn| {
+            let report = match db::reports::get_pending(conn, report_id)? {
+                Some(n) => n,
+                None => return Ok(None),
+            };
+
+            let resolution = match db::reports::resolve(
+                conn,
+                report_id,
+                resolver,
+                letter_status,
+                query.reason.as_deref(),
+                chrono::Utc::now().naive_utc(),
+            )? {
+                Some(n) => n,
+                None => return Ok(None),
+            };
+
+            let mut entry = NewAuditEntry::new(Some(resolver), AuditAction::ResolveReport)
+                .before(&report.letter)?
+                .after(&resolution.letter)?;
+            entry.report_id = Some(report_id);
+            entry.letter_id = Some(resolution.letter.id);
+            db::audit_log::insert(conn, entry)?;
+
+            Ok(Some(resolution.resolved_reports))
         })
-        .await?
-        .map_err(error::ErrorInternalServerError)?;
+    })
+    .await?;
+
+    let resolved = match resolved {
+        Some(n) => n,
+        None => return Err(error::ErrorNotFound("Report not found")),
+    };
+
+    log::info!(
+        "[resolve] {} resolved {} report(s) with {}",
+        resolver,
+        resolved,
+        report_id
+    );
 
-        Ok(HttpResponse::Accepted().json(json!({
-            "message": "Report resolved",
-        })))
-    }
+    Ok(HttpResponse::Accepted().json(json!({
+        "message": "Report resolved",
+        "resolved_reports": resolved,
+    })))
 }
 
 #[actix_web::delete("/api/reports/revoke/{id}")]
 pub async fn revoke(
-    restrictions: UserRestrictions,
+    restrictions: Authorized<ResolveReports>,
     pool: DbPool,
     id: web::Path<Uuid>,
 ) -> Result<impl Responder, ApiError> {
-    if !restrictions.moderator {
-        Err(error::ErrorForbidden("Not authorized to revoke reports"))
-    } else {
-        let pool = Arc::new(pool);
-        let pool_1 = pool.clone();
-
-        let id_1 = id.clone();
-
-        let report = web::block(move || {
-            let mut conn = pool.get()?;
-            db::reports::get_pending(&mut conn, id_1)
+    let report_id = id.into_inner();
+    let moderator = restrictions.user_id;
+    let revoked = db::run(&pool, move |conn| {
+        conn.transaction(|conn| {
+            let report = match db::reports::get_pending(conn, report_id)? {
+                Some(n) => n,
+                None => return Ok(false),
+            };
+
+            db::reports::delete(conn, report_id)?;
+
+            let mut entry =
+                NewAuditEntry::new(Some(moderator), AuditAction::RevokeReport).before(&report)?;
+            entry.report_id = Some(report_id);
+            entry.letter_id = Some(report.letter.id);
+            db::audit_log::insert(conn, entry)?;
+
+            Ok(true)
         })
-        .await?
-        .map_err(error::ErrorInternalServerError)?;
-
-        if report.is_none() {
-            return Err(error::ErrorNotFound("Report not found"));
-        }
-
-        web::block(move || {
-            let mut conn = pool_1.get()?;
-            db::reports::delete(&mut conn, id.into_inner())
-        })
-        .await?
-        .map_err(error::ErrorInternalServerError)?;
+    })
+    .await?;
 
-        Ok(HttpResponse::Accepted().json(json!({
-            "message": "Report revoked",
-        })))
+    if !revoked {
+        return Err(error::ErrorNotFound("Report not found"));
     }
+
+    Ok(HttpResponse::Accepted().json(json!({
+        "message": "Report revoked",
+    })))
 }
 
 #[actix_web::get("/api/reports/letters")]
 pub async fn get_pending_letters(
     auth_params: web::Data<AuthParams>,
-    restrictions: UserRestrictions,
+    restrictions: Authorized<ReadReports>,
     pool: DbPool,
     query: web::Query<RetrieveLetterQuery>,
 ) -> Result<impl Responder, ApiError> {
-    if !restrictions.moderator {
-        Err(error::ErrorForbidden("Not authorized to view reports"))
-    } else {
-        let mut reports = web::block(move || {
-            let mut conn = pool.get()?;
-            db::reports::get_all_pending(&mut conn, query.offset.unwrap_or_default())
-        })
-        .await?
-        .map_err(error::ErrorInternalServerError)?;
-
-        log::info!("[get_pending_letters] processing reports");
-        let encrypted_reports = reports.iter_mut().filter(|v| v.letter.secret);
-        for report in encrypted_reports {
-            log::info!(
-                "[get_pending_letters] secret letter found (id = {}); decrypting...",
-                report.letter.id
-            );
-            let message = decrypt_message(
-                &auth_params.secret_key,
-                &report.letter.author,
-                &report.letter.message,
-            )
-            .await
-            .map_err(|e| {
-                log::error!(
-                    "[get_pending_letters] failed to decrypt report letter message (id = {:?}): {}",
-                    report.letter.id,
-                    e
-                );
-                ApiError::we_pretend_why_it_does_error()
-            })?;
-            log::info!(
-                "[get_pending_letters] done decrypting report letter = {}",
-                report.letter.id
-            );
-            report.letter.message = message;
-        }
+    let mut reports = db::run(&pool, move |conn| {
+        db::reports::get_all_pending(conn, query.offset.unwrap_or_default())
+    })
+    .await?;
 
-        Ok(HttpResponse::Ok().json(reports))
+    if !restrictions.has(Permission::ReadSecretLetters) {
+        return Ok(HttpResponse::Ok().json(reports));
     }
+
+    log::info!("[get_pending_letters] processing reports");
+    let encrypted_reports = reports.iter_mut().filter(|v| v.letter.secret);
+    for report in encrypted_reports {
+        log::info!(
+            "[get_pending_letters] secret letter found (id = {}); decrypting...",
+            report.letter.id
+        );
+        let message = decrypt_message(
+            &auth_params.secret_keys,
+            &report.letter.author,
+            &report.letter.message,
+        )
+        .await
+        .map_err(|e| {
+            log::error!(
+                "[get_pending_letters] failed to decrypt report letter message (id = {:?}): {}",
+                report.letter.id,
+                e
+            );
+            ApiError::we_pretend_why_it_does_error()
+        })?;
+        log::info!(
+            "[get_pending_letters] done decrypting report letter = {}",
+            report.letter.id
+        );
+        report.letter.message = message;
+    }
+
+    Ok(HttpResponse::Ok().json(reports))
 }
 
 #[derive(Deserialize)]
@@ -168,6 +189,7 @@ pub struct ReportLetterForm {
 
 #[actix_web::post("/api/letters/report/{uuid}")]
 pub async fn report_letter(
+    config: web::Data<Config>,
     letter_uid: web::Path<Uuid>,
     pool: DbPool,
     form: web::Json<ReportLetterForm>,
@@ -178,12 +200,7 @@ pub async fn report_letter(
     let uuid = Arc::new(letter_uid.into_inner());
     let uuid_1 = uuid.clone();
 
-    let letter = web::block(move || {
-        let mut conn = pool_1.get()?;
-        db::letters::find_by_id(&mut conn, &*uuid_1)
-    })
-    .await?
-    .map_err(error::ErrorInternalServerError)?;
+    let letter = db::run(&pool_1, move |conn| db::letters::find_by_id(conn, &uuid_1)).await?;
 
     if letter.is_none() {
         log::warn!("[letter] non-existing letter id = {:?}", uuid);
@@ -194,16 +211,12 @@ pub async fn report_letter(
 
     log::debug!("[letter] existing letter id = {:?}", uuid);
 
-    static MIN_EMAIL_LEN: usize = 3;
-    static MAX_EMAIL_LEN: usize = 50;
-
-    static MAX_DETAILS_LEN: usize = 1000;
-
     let email = ammonia::clean(&form.email);
     let details = ammonia::clean(&form.details);
 
-    create_test_fn!(test_email, MAX_DETAILS_LEN, MIN_EMAIL_LEN);
-    create_test_fn!(test_details, MAX_DETAILS_LEN);
+    let limits = &config.limits;
+    create_test_fn!(test_email, limits.max_email_len, limits.min_email_len);
+    create_test_fn!(test_details, limits.max_details_len);
 
     test_contraints!(
         test_email,
@@ -218,12 +231,10 @@ pub async fn report_letter(
         "Details field is too small"
     );
 
-    let report = web::block(move || {
-        let mut conn = pool.get()?;
-        db::reports::insert(&mut conn, *uuid, &form.email, &form.details, form.type_)
+    let report = db::run(&pool, move |conn| {
+        db::reports::insert(conn, *uuid, &form.email, &form.details, form.type_)
     })
-    .await?
-    .map_err(error::ErrorInternalServerError)?;
+    .await?;
 
     Ok(HttpResponse::Created().json(json!({
         "id": report.id,
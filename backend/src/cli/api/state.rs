@@ -0,0 +1,106 @@
+use actix_web::web::{self, ServiceConfig};
+use actix_web::{HttpResponse, Responder};
+
+use backend_lib::config::Config;
+use backend_lib::db::{self, DbPool};
+use backend_lib::models::{AuditAction, NewAuditEntry, State, UpdateState};
+use backend_lib::reqs::permission::{Authorized, ToggleState};
+use backend_lib::resp::error::{self, ApiError};
+
+use chrono::NaiveDateTime;
+use diesel::Connection;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{create_test_fn, test_contraints};
+
+fn state_json(state: Option<&State>) -> Value {
+    let now = chrono::Utc::now().naive_utc();
+    let available = state.map(|v| v.is_available_at(now)).unwrap_or_default();
+
+    // countdowns are in seconds so clients don't have to care
+    // about which timezone the server is in
+    json!({
+        "available": available,
+        "opens_at": state.and_then(|v| v.opens_at),
+        "closes_at": state.and_then(|v| v.closes_at),
+        "opens_in": state.and_then(|v| v.opens_in(now)).map(|v| v.num_seconds()),
+        "closes_in": state.and_then(|v| v.closes_in(now)).map(|v| v.num_seconds()),
+        "message": state.and_then(|v| v.closed_message.as_deref()),
+        "premoderation": state.map(|v| v.premoderation).unwrap_or_default(),
+    })
+}
+
+#[actix_web::get("/api/available")]
+pub async fn is_available(pool: DbPool) -> Result<impl Responder, ApiError> {
+    let state = db::run(&pool, db::state::get).await?;
+
+    Ok(HttpResponse::Ok().json(state_json(state.as_ref())))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetAvailableForm {
+    pub available: bool,
+    /// When to start accepting submissions automatically (in UTC)
+    pub opens_at: Option<NaiveDateTime>,
+    /// When to stop accepting submissions automatically (in UTC)
+    pub closes_at: Option<NaiveDateTime>,
+    /// Shown to visitors once submissions are closed
+    pub message: Option<String>,
+}
+
+#[actix_web::put("/api/available")]
+pub async fn set_available(
+    config: web::Data<Config>,
+    restrictions: Authorized<ToggleState>,
+    pool: DbPool,
+    form: web::Json<SetAvailableForm>,
+) -> Result<impl Responder, ApiError> {
+    create_test_fn!(test_closed_message, config.limits.max_closed_message_len);
+
+    let form = form.into_inner();
+    let message = form.message.map(|v| ammonia::clean(v.trim()));
+    if let Some(message) = message.as_deref() {
+        test_contraints!(
+            test_closed_message,
+            message,
+            "Message field is too big",
+            "Message field is too small"
+        );
+    }
+
+    let new_state = UpdateState {
+        available: form.available,
+        opens_at: form.opens_at,
+        closes_at: form.closes_at,
+        closed_message: message,
+    };
+    new_state
+        .validate(chrono::Utc::now().naive_utc())
+        .map_err(error::ErrorBadRequest)?;
+
+    let actor = restrictions.user_id;
+    let state = db::run(&pool, move |conn| {
+        conn.transaction(|conn| {
+            let before = db::state::get(conn)?;
+            let state = db::state::set_available(conn, new_state)?;
+
+            let entry = NewAuditEntry::new(Some(actor), AuditAction::SetAvailability)
+                .before(&before)?
+                .after(&state)?;
+            db::audit_log::insert(conn, entry)?;
+            Ok(state)
+        })
+    })
+    .await?;
+
+    log::info!(
+        "[set_available] submissions are now {}",
+        if state.available { "open" } else { "closed" }
+    );
+    Ok(HttpResponse::Ok().json(state_json(Some(&state))))
+}
+
+pub fn apply(cfg: &mut ServiceConfig) {
+    cfg.service(is_available).service(set_available);
+}
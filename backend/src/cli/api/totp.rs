@@ -0,0 +1,358 @@
+use std::sync::Arc;
+
+use actix_web::web::{self, ServiceConfig};
+use actix_web::{HttpRequest, HttpResponse, Responder};
+
+use backend_lib::config::{AuthParams, Config};
+use backend_lib::db::{self, DbPool};
+use backend_lib::models::{AuditAction, NewAuditEntry, TwoFactorChallenge};
+use backend_lib::reqs::permission::{Authorized, ManageUsers};
+use backend_lib::reqs::user::UserRestrictions;
+use backend_lib::resp::error::{self, ApiError};
+use backend_lib::utils::password::verify_password;
+use backend_lib::utils::{token, totp};
+
+use diesel::{Connection, PgConnection};
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use super::users::start_session;
+use crate::ratelimit::LoginThrottle;
+
+static INVALID_CODE: &str = "Invalid code";
+
+/// Checks either a code from the authenticator app or
+/// a recovery code, both can only be used once.
+fn use_code(conn: &mut PgConnection, user_id: Uuid, code: &str) -> anyhow::Result<bool> {
+    let user_totp = match db::totp::find(conn, user_id)? {
+        Some(n) if n.is_enabled() => n,
+        _ => return Ok(false),
+    };
+
+    let code = code.trim();
+    if code.len() == totp::TOTP_DIGITS as usize {
+        let now = chrono::Utc::now().timestamp();
+        return match totp::verify(&user_totp.secret, code, now, user_totp.last_used_step)? {
+            Some(step) => db::totp::use_step(conn, user_id, step),
+            None => Ok(false),
+        };
+    }
+
+    let hash = token::hash(&totp::normalize_recovery_code(code));
+    let used = db::totp::use_recovery_code(conn, user_id, &hash, chrono::Utc::now().naive_utc())?;
+    if used {
+        log::info!("[use_code] {} used a recovery code", user_id);
+    }
+    Ok(used)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginTotpForm {
+    /// Given by `/api/users/login` after checking the password
+    challenge: String,
+    code: String,
+}
+
+/// Second step of logging in for users with two-factor authentication
+#[actix_web::post("/api/users/login/totp")]
+pub async fn login(
+    req: HttpRequest,
+    pool: DbPool,
+    auth_params: web::Data<AuthParams>,
+    config: web::Data<Config>,
+    form: web::Json<LoginTotpForm>,
+) -> Result<impl Responder, ApiError> {
+    let challenge =
+        TwoFactorChallenge::decode_token(&form.challenge, &auth_params.token).map_err(|e| {
+            log::debug!("[login_totp] failed to decode challenge: {}", e);
+            error::ErrorUnauthorized("Login expired, please log in again")
+        })?;
+
+    let user_id = Uuid::parse_str(&challenge.sub).map_err(|e| {
+        log::error!("[login_totp] invalid user id in challenge: {}", e);
+        ApiError::we_pretend_why_it_does_error()
+    })?;
+
+    let pool = Arc::new(pool);
+    let pool_1 = pool.clone();
+    let pool_2 = pool.clone();
+
+    let user = db::run(&pool, move |conn| db::users::find_by_id(conn, &user_id))
+        .await?
+        .ok_or_else(|| error::ErrorUnauthorized("Login expired, please log in again"))?;
+
+    // wrong codes count along with wrong passwords
+    let throttle = LoginThrottle::new(&config, &req, &user.name);
+    throttle.check(&pool_1).await?;
+
+    let form = form.into_inner();
+    let valid = db::run(&pool_1, move |conn| use_code(conn, user_id, &form.code)).await?;
+
+    if !valid {
+        let err = error::ErrorUnauthorized(INVALID_CODE);
+        return Err(throttle.failed(&pool_2, err).await);
+    }
+
+    throttle.succeeded(&pool_2).await?;
+    start_session(pool_2, &config, &auth_params, &user).await
+}
+
+#[actix_web::get("/api/users/me/totp")]
+pub async fn status(
+    restrictions: UserRestrictions,
+    pool: DbPool,
+) -> Result<impl Responder, ApiError> {
+    let user_id = restrictions.user_id;
+    let (enabled, recovery_codes_left) = db::run(&pool, move |conn| -> anyhow::Result<_> {
+        let enabled = db::totp::is_enabled(conn, user_id)?;
+        let left = db::totp::count_unused_recovery_codes(conn, user_id)?;
+        Ok((enabled, left))
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "enabled": enabled,
+        "recovery_codes_left": recovery_codes_left,
+    })))
+}
+
+/// Generates the secret to be added to the authenticator app, it's
+/// enabled only after a code is confirmed with `/confirm`.
+#[actix_web::post("/api/users/me/totp")]
+pub async fn enroll(
+    restrictions: UserRestrictions,
+    pool: DbPool,
+    config: web::Data<Config>,
+) -> Result<impl Responder, ApiError> {
+    let user_id = restrictions.user_id;
+    let secret = totp::generate_secret();
+    let secret_1 = secret.clone();
+
+    enum Enrolled {
+        Started(String),
+        AlreadyEnabled,
+        UserNotFound,
+    }
+
+    let enrolled = db::run(&pool, move |conn| -> anyhow::Result<Enrolled> {
+        let user = match db::users::find_by_id(conn, &user_id)? {
+            Some(n) => n,
+            None => return Ok(Enrolled::UserNotFound),
+        };
+
+        let now = chrono::Utc::now().naive_utc();
+        if !db::totp::start_enrollment(conn, user_id, &secret_1, now)? {
+            return Ok(Enrolled::AlreadyEnabled);
+        }
+        Ok(Enrolled::Started(user.name))
+    })
+    .await?;
+
+    match enrolled {
+        Enrolled::Started(name) => Ok(HttpResponse::Ok().json(json!({
+            "secret": secret,
+            "uri": totp::provisioning_uri(&config.totp.issuer, &name, &secret),
+        }))),
+        Enrolled::AlreadyEnabled => Err(error::ErrorConflict(
+            "Two-factor authentication is already enabled",
+        )),
+        Enrolled::UserNotFound => Err(error::ErrorUnauthorized("Unauthorized")),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CodeForm {
+    code: String,
+}
+
+/// Enables two-factor authentication once the user proves their app
+/// works, giving them recovery codes which are only shown once.
+#[actix_web::post("/api/users/me/totp/confirm")]
+pub async fn confirm(
+    restrictions: UserRestrictions,
+    pool: DbPool,
+    form: web::Json<CodeForm>,
+) -> Result<impl Responder, ApiError> {
+    let user_id = restrictions.user_id;
+    let recovery_codes = totp::generate_recovery_codes();
+    let hashes = recovery_codes
+        .iter()
+        .map(|v| token::hash(&totp::normalize_recovery_code(v)))
+        .collect::<Vec<_>>();
+
+    enum Confirmed {
+        Enabled,
+        NotEnrolled,
+        InvalidCode,
+    }
+
+    let confirmed = db::run(&pool, move |conn| -> anyhow::Result<Confirmed> {
+        let user_totp = match db::totp::find(conn, user_id)? {
+            Some(n) if !n.is_enabled() => n,
+            _ => return Ok(Confirmed::NotEnrolled),
+        };
+
+        let now = chrono::Utc::now();
+        let step = match totp::verify(&user_totp.secret, &form.code, now.timestamp(), None)? {
+            Some(n) => n,
+            None => return Ok(Confirmed::InvalidCode),
+        };
+
+        if !db::totp::enable(conn, user_id, step, &hashes, now.naive_utc())? {
+            return Ok(Confirmed::NotEnrolled);
+        }
+        Ok(Confirmed::Enabled)
+    })
+    .await?;
+
+    match confirmed {
+        Confirmed::Enabled => {
+            log::info!("[confirm] {} enabled two-factor authentication", user_id);
+            Ok(HttpResponse::Ok().json(json!({
+                "recovery_codes": recovery_codes,
+            })))
+        }
+        Confirmed::NotEnrolled => Err(error::ErrorConflict(
+            "Two-factor authentication is not being set up",
+        )),
+        Confirmed::InvalidCode => Err(error::ErrorForbidden(INVALID_CODE)),
+    }
+}
+
+/// Replaces every recovery code, in case they're lost or running out
+#[actix_web::post("/api/users/me/totp/recovery-codes")]
+pub async fn regenerate_recovery_codes(
+    restrictions: UserRestrictions,
+    pool: DbPool,
+    form: web::Json<CodeForm>,
+) -> Result<impl Responder, ApiError> {
+    let user_id = restrictions.user_id;
+    let recovery_codes = totp::generate_recovery_codes();
+    let hashes = recovery_codes
+        .iter()
+        .map(|v| token::hash(&totp::normalize_recovery_code(v)))
+        .collect::<Vec<_>>();
+
+    let replaced = db::run(&pool, move |conn| -> anyhow::Result<bool> {
+        if !use_code(conn, user_id, &form.code)? {
+            return Ok(false);
+        }
+        db::totp::replace_recovery_codes(conn, user_id, &hashes)?;
+        Ok(true)
+    })
+    .await?;
+
+    if !replaced {
+        return Err(error::ErrorForbidden(INVALID_CODE));
+    }
+
+    Ok(HttpResponse::Ok().json(json!({
+        "recovery_codes": recovery_codes,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DisableForm {
+    password: String,
+}
+
+#[actix_web::delete("/api/users/me/totp")]
+pub async fn disable(
+    req: HttpRequest,
+    restrictions: UserRestrictions,
+    pool: DbPool,
+    auth_params: web::Data<AuthParams>,
+    config: web::Data<Config>,
+    form: web::Json<DisableForm>,
+) -> Result<impl Responder, ApiError> {
+    let pool = Arc::new(pool);
+    let pool_1 = pool.clone();
+    let pool_2 = pool.clone();
+
+    let user_id = restrictions.user_id;
+    let user = db::run(&pool, move |conn| db::users::find_by_id(conn, &user_id))
+        .await?
+        .ok_or_else(|| error::ErrorUnauthorized("Unauthorized"))?;
+
+    // a stolen session mustn't be able to guess the password
+    let throttle = LoginThrottle::new(&config, &req, &user.name);
+    throttle.check(&pool_1).await?;
+
+    let legacy_salt = auth_params.salt;
+    let password_config = config.password.clone();
+    let verified = web::block(move || {
+        verify_password(
+            &password_config,
+            &legacy_salt,
+            &user.name,
+            &form.password,
+            &user.password,
+        )
+    })
+    .await?;
+
+    if !verified.is_valid() {
+        let err = error::ErrorForbidden("Password is incorrect");
+        return Err(throttle.failed(&pool_2, err).await);
+    }
+
+    throttle.succeeded(&pool_2).await?;
+    let disabled = db::run(&pool_2, move |conn| db::totp::disable(conn, user_id)).await?;
+
+    if !disabled {
+        return Err(error::ErrorNotFound(
+            "Two-factor authentication is not enabled",
+        ));
+    }
+
+    log::info!("[disable] {} disabled two-factor authentication", user_id);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// For users who lost both their authenticator app and recovery codes
+#[actix_web::delete("/api/users/{id}/totp")]
+pub async fn reset(
+    restrictions: Authorized<ManageUsers>,
+    pool: DbPool,
+    id: web::Path<Uuid>,
+) -> Result<impl Responder, ApiError> {
+    let user_id = id.into_inner();
+    let actor = restrictions.user_id;
+    let disabled = db::run(&pool, move |conn| -> anyhow::Result<bool> {
+        conn.transaction(|conn| {
+            if !db::totp::disable(conn, user_id)? {
+                return Ok(false);
+            }
+
+            let mut entry = NewAuditEntry::new(Some(actor), AuditAction::ResetUserTotp);
+            entry.user_id = Some(user_id);
+            db::audit_log::insert(conn, entry)?;
+            Ok(true)
+        })
+    })
+    .await?;
+
+    if !disabled {
+        return Err(error::ErrorNotFound(
+            "Two-factor authentication is not enabled",
+        ));
+    }
+
+    log::info!(
+        "[reset] {} reset two-factor authentication of {}",
+        actor,
+        user_id
+    );
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn apply(cfg: &mut ServiceConfig) {
+    cfg.service(login)
+        .service(status)
+        .service(enroll)
+        .service(confirm)
+        .service(regenerate_recovery_codes)
+        .service(disable)
+        .service(reset);
+}
@@ -1,23 +1,36 @@
 use std::sync::Arc;
 
 use actix_web::cookie::time::Duration;
-use actix_web::cookie::{Cookie, SameSite};
+use actix_web::cookie::Cookie;
 
 use actix_web::web::{self, ServiceConfig};
-use actix_web::{HttpResponse, Responder};
+use actix_web::{HttpRequest, HttpResponse, Responder};
 
-use anyhow::Context;
-
-use backend_lib::config::AuthParams;
+use backend_lib::config::{AuthParams, Config};
+use backend_lib::db::invites::Redeemed;
 use backend_lib::db::{self, DbPool};
-use backend_lib::models::{NewUser, UserToken, TOKEN_EXPIRY_DURATION};
+use backend_lib::models::{
+    refresh_token_expiry, AuditAction, NewAuditEntry, NewSession, NewUser, Permission, Session,
+    TwoFactorChallenge, User, UserInfo, UserToken, REFRESH_TOKEN_EXPIRY_DURATION,
+    TOKEN_EXPIRY_DURATION, TWO_FACTOR_CHALLENGE_EXPIRY_DURATION,
+};
 
+use backend_lib::reqs::permission::{Authorized, ManageUsers};
 use backend_lib::reqs::register::RegisterAuth;
+use backend_lib::reqs::user::UserRestrictions;
+use backend_lib::reqs::{REFRESH_TOKEN_COOKIE, TOKEN_COOKIE};
 use backend_lib::resp::error::{self, ApiError};
+use backend_lib::utils::password::{
+    hash_password, is_legacy_hash, verify_missing_user_password, verify_password, Verified,
+};
+use backend_lib::utils::token;
 
+use diesel::Connection;
 use serde::Deserialize;
 use serde_json::json;
+use uuid::Uuid;
 
+use crate::ratelimit::LoginThrottle;
 use crate::{create_test_fn, test_contraints};
 
 #[derive(Debug, Deserialize)]
@@ -26,23 +39,25 @@ pub struct UserForm {
     password: String,
 }
 
-static MAX_USERNAME_LEN: usize = 50;
-
-static MIN_PASSWORD_LEN: usize = 12;
-static MAX_PASSWORD_LEN: usize = 50;
-
 static INVALID_CREDIENTALS: &str = "Invalid credientials";
+static INVALID_INVITE: &str = "Invalid or expired invite";
 
-create_test_fn!(test_password, MAX_PASSWORD_LEN, MIN_PASSWORD_LEN);
-create_test_fn!(test_username, MAX_USERNAME_LEN);
-
+/// Creates an account with an invite, which gives its roles to the user
 #[actix_web::post("/api/users/register")]
 pub async fn register(
-    _authorized: RegisterAuth,
+    authorized: RegisterAuth,
     pool: DbPool,
-    auth_params: web::Data<AuthParams>,
+    config: web::Data<Config>,
     form: web::Json<UserForm>,
 ) -> Result<impl Responder, ApiError> {
+    let limits = &config.limits;
+    create_test_fn!(
+        test_password,
+        limits.max_password_len,
+        limits.min_password_len
+    );
+    create_test_fn!(test_username, limits.max_username_len);
+
     test_contraints!(
         test_username,
         &form.username,
@@ -56,47 +71,49 @@ pub async fn register(
         "password too short"
     );
 
-    // check for any duplications
-    let form = Arc::new(form);
-    let form_1 = form.clone();
-
+    // checked early so invalid invites don't cost a password hash,
+    // it's only used up once the user is created
     let pool = Arc::new(pool);
     let pool_1 = pool.clone();
-
-    let user = web::block(move || {
-        let mut conn = pool.get()?;
-        db::users::find_by_username(&mut conn, &*form_1.username)
+    let invite_code_hash = Arc::new(authorized.invite_code_hash);
+    let invite_code_hash_1 = invite_code_hash.clone();
+    let invite = db::run(&pool_1, move |conn| {
+        db::invites::find_usable(conn, &invite_code_hash_1, chrono::Utc::now().naive_utc())
     })
-    .await?
-    .map_err(error::ErrorInternalServerError)?;
+    .await?;
 
-    if user.is_some() {
-        return Err(error::ErrorConflict("Already registered!"));
+    if invite.is_none() {
+        return Err(error::ErrorUnauthorized(INVALID_INVITE));
     }
 
-    let formed_password = format!("{}{:?}{}", form.username, auth_params.salt, form.password);
-
-    // encrypt the password and add some salting into it
-    // hackers will happy to investigate because of AGPL license
-    let parts = bcrypt::hash_with_salt(&formed_password, 10, auth_params.salt.clone())
-        .with_context(|| "failed to hash password")
+    let form = Arc::new(form);
+    let form_1 = form.clone();
+    let password_config = config.password.clone();
+    let password = web::block(move || hash_password(&password_config, &form_1.password))
+        .await?
         .map_err(|e| {
             log::error!("[register] failed to generate password hash: {}", e);
             ApiError::we_pretend_why_it_does_error()
         })?;
 
-    let user = web::block(move || {
-        let mut conn = pool_1.get()?;
-        db::users::insert(
-            &mut conn,
+    let redeemed = db::run(&pool, move |conn| {
+        db::invites::redeem(
+            conn,
+            &invite_code_hash,
             NewUser {
                 name: &form.username,
-                password: &parts.to_string(),
+                password: &password,
             },
+            chrono::Utc::now().naive_utc(),
         )
     })
-    .await?
-    .map_err(error::ErrorInternalServerError)?;
+    .await?;
+
+    let user = match redeemed {
+        Redeemed::Registered(n) => n,
+        Redeemed::InvalidInvite => return Err(error::ErrorUnauthorized(INVALID_INVITE)),
+        Redeemed::UsernameTaken => return Err(error::ErrorConflict("Already registered!")),
+    };
 
     Ok(HttpResponse::Created().json(json!({
         "id": user.id,
@@ -106,10 +123,19 @@ pub async fn register(
 
 #[actix_web::post("/api/users/login")]
 pub async fn login(
+    req: HttpRequest,
     pool: DbPool,
     auth_params: web::Data<AuthParams>,
+    config: web::Data<Config>,
     form: web::Json<UserForm>,
 ) -> Result<impl Responder, ApiError> {
+    let limits = &config.limits;
+    create_test_fn!(
+        test_password,
+        limits.max_password_len,
+        limits.min_password_len
+    );
+
     test_contraints!(
         test_password,
         &form.password,
@@ -117,53 +143,837 @@ pub async fn login(
         INVALID_CREDIENTALS
     );
 
+    let throttle = LoginThrottle::new(&config, &req, &form.username);
+    throttle.check(&pool).await?;
+
     let form = Arc::new(form);
     let form_1 = form.clone();
 
     let pool = Arc::new(pool);
-    let user = web::block(move || {
-        let mut conn = pool.get()?;
-        db::users::find_by_username(&mut conn, &*form_1.username)
+    let pool_1 = pool.clone();
+    let pool_2 = pool.clone();
+    let pool_3 = pool.clone();
+    let pool_4 = pool.clone();
+
+    let user = db::run(&pool, move |conn| {
+        db::users::find_by_username(conn, &*form_1.username)
     })
-    .await?
-    .map_err(error::ErrorInternalServerError)?;
+    .await?;
 
     let user = match user {
         Some(n) => n,
-        None => return Err(error::ErrorUnauthorized(INVALID_CREDIENTALS)),
+        None => {
+            let form_2 = form.clone();
+            let password_config = config.password.clone();
+            web::block(move || verify_missing_user_password(&password_config, &form_2.password))
+                .await?;
+
+            let err = error::ErrorUnauthorized(INVALID_CREDIENTALS);
+            return Err(throttle.failed(&pool_4, err).await);
+        }
     };
 
-    let formed_password = format!("{}{:?}{}", form.username, auth_params.salt, form.password);
-    if !bcrypt::verify(&formed_password, &user.password).unwrap_or_default() {
-        return Err(error::ErrorUnauthorized(INVALID_CREDIENTALS));
+    let form_2 = form.clone();
+    let hash = user.password.clone();
+    let legacy_salt = auth_params.salt;
+    let password_config = config.password.clone();
+    let verified = web::block(move || {
+        verify_password(
+            &password_config,
+            &legacy_salt,
+            &form_2.username,
+            &form_2.password,
+            &hash,
+        )
+    })
+    .await?;
+
+    if !verified.is_valid() {
+        let err = error::ErrorUnauthorized(INVALID_CREDIENTALS);
+        return Err(throttle.failed(&pool_4, err).await);
     }
 
-    let token =
-        UserToken::generate_token(&user.id.to_string(), &auth_params.token).map_err(|e| {
-            log::error!("[login] failed to generate jwt token: {}", e);
-            ApiError::we_pretend_why_it_does_error()
-        })?;
+    // older hashes are replaced while we still know the password
+    if verified == Verified::NeedsRehash {
+        let user_id = user.id;
+        let password_config = config.password.clone();
+        let password = web::block(move || hash_password(&password_config, &form.password)).await?;
+        let result = match password {
+            Ok(password) => db::run(&pool_2, move |conn| {
+                db::users::update_password(conn, user_id, &password)
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e)),
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(()) => log::info!("[login] rehashed password of {}", user_id),
+            Err(e) => log::error!("[login] failed to rehash password of {}: {}", user_id, e),
+        }
+    }
 
+    let user_id = user.id;
+    let has_2fa = db::run(&pool_1, move |conn| db::totp::is_enabled(conn, user_id)).await?;
+
+    // the session is only created after the second factor, failures
+    // are only forgotten then so they add up with wrong codes
+    if has_2fa {
+        let challenge = TwoFactorChallenge::generate_token(user_id.to_string(), &auth_params.token)
+            .map_err(|e| {
+                log::error!("[login] failed to generate challenge: {}", e);
+                ApiError::we_pretend_why_it_does_error()
+            })?;
+
+        return Ok(HttpResponse::Ok().json(json!({
+            "two_factor_required": true,
+            "challenge": challenge,
+            "expires_in": TWO_FACTOR_CHALLENGE_EXPIRY_DURATION,
+        })));
+    }
+
+    throttle.succeeded(&pool_4).await?;
+    start_session(pool_3, &config, &auth_params, &user).await
+}
+
+/// Logs the user in, after every factor is verified
+pub(crate) async fn start_session(
+    pool: Arc<DbPool>,
+    config: &Config,
+    auth_params: &AuthParams,
+    user: &User,
+) -> Result<HttpResponse, ApiError> {
+    let refresh_token = token::generate();
+    let refresh_hash = token::hash(&refresh_token);
+
+    let user_id = user.id;
+    let (session, roles, permissions, roles_missing_2fa) =
+        db::run(&pool, move |conn| -> anyhow::Result<_> {
+            let session = db::sessions::insert(
+                conn,
+                NewSession {
+                    user_id,
+                    expires_at: refresh_token_expiry(chrono::Utc::now().naive_utc()),
+                    refresh_token: &refresh_hash,
+                },
+            )?;
+
+            Ok((
+                session,
+                db::roles::roles_of_user(conn, user_id)?,
+                db::roles::permissions_of_user(conn, user_id)?,
+                db::roles::roles_missing_2fa(conn, user_id)?,
+            ))
+        })
+        .await?;
+
+    // these roles don't do anything until 2FA is set up
     let mut response = HttpResponse::Accepted().json(json!({
         "id": user.id,
         "created_at": user.created_at,
-        "moderator": user.moderator,
-        "viewer": user.viewer,
+        "roles": roles,
+        "permissions": permissions,
+        "roles_missing_2fa": roles_missing_2fa,
     }));
 
+    add_session_cookies(&mut response, config, auth_params, &session, &refresh_token)?;
+    Ok(response)
+}
+
+// browsers only need to send the refresh token to these endpoints
+static REFRESH_TOKEN_PATH: &str = "/api/users";
+
+fn add_session_cookies(
+    response: &mut HttpResponse,
+    config: &Config,
+    auth_params: &AuthParams,
+    session: &Session,
+    refresh_token: &str,
+) -> Result<(), ApiError> {
+    let token = UserToken::generate_token(
+        session.user_id.to_string(),
+        session.id.to_string(),
+        &auth_params.token,
+    )
+    .map_err(|e| {
+        log::error!("[session] failed to generate jwt token: {}", e);
+        ApiError::we_pretend_why_it_does_error()
+    })?;
+
+    let cookies = &config.cookies;
     response
         .add_cookie(
-            &Cookie::build("token", token)
+            &Cookie::build(TOKEN_COOKIE, token)
                 .max_age(Duration::seconds(TOKEN_EXPIRY_DURATION))
-                .same_site(SameSite::Lax)
+                .same_site(cookies.same_site.into())
+                .secure(cookies.secure)
+                .http_only(cookies.http_only)
                 .path("/")
                 .finish(),
         )
         .map_err(error::ErrorInternalServerError)?;
 
+    // scripts never need the refresh token
+    response
+        .add_cookie(
+            &Cookie::build(REFRESH_TOKEN_COOKIE, refresh_token)
+                .max_age(Duration::seconds(REFRESH_TOKEN_EXPIRY_DURATION))
+                .same_site(cookies.same_site.into())
+                .secure(cookies.secure)
+                .http_only(true)
+                .path(REFRESH_TOKEN_PATH)
+                .finish(),
+        )
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(())
+}
+
+fn remove_session_cookies(response: &mut HttpResponse) -> Result<(), ApiError> {
+    response
+        .add_removal_cookie(&Cookie::build(TOKEN_COOKIE, "").path("/").finish())
+        .map_err(error::ErrorInternalServerError)?;
+
+    response
+        .add_removal_cookie(
+            &Cookie::build(REFRESH_TOKEN_COOKIE, "")
+                .path(REFRESH_TOKEN_PATH)
+                .finish(),
+        )
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RefreshForm {
+    /// For clients without cookies, browsers use the cookie instead
+    refresh_token: Option<String>,
+}
+
+enum Refreshed {
+    Rotated(Session),
+    Reused(Uuid),
+    Invalid,
+}
+
+#[actix_web::post("/api/users/refresh")]
+pub async fn refresh(
+    req: HttpRequest,
+    pool: DbPool,
+    auth_params: web::Data<AuthParams>,
+    config: web::Data<Config>,
+    form: Option<web::Json<RefreshForm>>,
+) -> Result<impl Responder, ApiError> {
+    let token = form
+        .and_then(|v| v.into_inner().refresh_token)
+        .or_else(|| {
+            req.cookie(REFRESH_TOKEN_COOKIE)
+                .map(|v| v.value().to_string())
+        })
+        .ok_or_else(|| error::ErrorUnauthorized("Unauthorized"))?;
+
+    let old_hash = token::hash(&token);
+    let refresh_token = token::generate();
+    let new_hash = token::hash(&refresh_token);
+
+    let refreshed = db::run(&pool, move |conn| -> anyhow::Result<Refreshed> {
+        let now = chrono::Utc::now().naive_utc();
+
+        let session = match db::sessions::find_by_refresh_token(conn, &old_hash)? {
+            Some(n) => n,
+            None => return Ok(Refreshed::Invalid),
+        };
+
+        // it's already exchanged for a new one, someone else has it
+        if session.refresh_token != old_hash {
+            db::sessions::revoke(conn, session.id, now)?;
+            return Ok(Refreshed::Reused(session.id));
+        }
+
+        if !session.is_active_at(now) {
+            return Ok(Refreshed::Invalid);
+        }
+
+        let session = db::sessions::rotate(
+            conn,
+            session.id,
+            &old_hash,
+            &new_hash,
+            refresh_token_expiry(now),
+            now,
+        )?;
+
+        Ok(session
+            .map(Refreshed::Rotated)
+            .unwrap_or(Refreshed::Invalid))
+    })
+    .await?;
+
+    let session = match refreshed {
+        Refreshed::Rotated(n) => n,
+        Refreshed::Reused(id) => {
+            log::warn!(
+                "[refresh] refresh token of session {} is reused, revoking the session",
+                id
+            );
+            return Err(error::ErrorUnauthorized("Unauthorized"));
+        }
+        Refreshed::Invalid => return Err(error::ErrorUnauthorized("Unauthorized")),
+    };
+
+    let mut response = HttpResponse::Ok().json(json!({
+        "expires_in": TOKEN_EXPIRY_DURATION,
+    }));
+
+    add_session_cookies(
+        &mut response,
+        &config,
+        &auth_params,
+        &session,
+        &refresh_token,
+    )?;
     Ok(response)
 }
 
+#[actix_web::post("/api/users/logout")]
+pub async fn logout(
+    req: HttpRequest,
+    restrictions: Option<UserRestrictions>,
+    pool: DbPool,
+) -> Result<impl Responder, ApiError> {
+    // the access token may be expired already, so the
+    // refresh token is used to find the session too
+    let token_hash = req
+        .cookie(REFRESH_TOKEN_COOKIE)
+        .map(|v| token::hash(v.value()));
+
+    let session_id = restrictions.map(|v| v.session_id);
+    db::run(&pool, move |conn| -> anyhow::Result<()> {
+        let now = chrono::Utc::now().naive_utc();
+
+        let session_id = match (session_id, token_hash) {
+            (Some(id), _) => Some(id),
+            (None, Some(hash)) => db::sessions::find_by_refresh_token(conn, &hash)?
+                .filter(|v| v.refresh_token == hash)
+                .map(|v| v.id),
+            (None, None) => None,
+        };
+
+        if let Some(id) = session_id {
+            db::sessions::revoke(conn, id, now)?;
+        }
+        Ok(())
+    })
+    .await?;
+
+    let mut response = HttpResponse::NoContent().finish();
+    remove_session_cookies(&mut response)?;
+    Ok(response)
+}
+
+/// Logs out from every device
+#[actix_web::post("/api/users/logout/all")]
+pub async fn logout_all(
+    restrictions: UserRestrictions,
+    pool: DbPool,
+) -> Result<impl Responder, ApiError> {
+    let user_id = restrictions.user_id;
+    let revoked = db::run(&pool, move |conn| {
+        db::sessions::revoke_all(conn, user_id, chrono::Utc::now().naive_utc())
+    })
+    .await?;
+
+    log::info!("[logout_all] revoked {} sessions of {}", revoked, user_id);
+
+    let mut response = HttpResponse::NoContent().finish();
+    remove_session_cookies(&mut response)?;
+    Ok(response)
+}
+
+#[actix_web::get("/api/users/me")]
+pub async fn me(restrictions: UserRestrictions, pool: DbPool) -> Result<impl Responder, ApiError> {
+    let user_id = restrictions.user_id;
+    let user = db::run(&pool, move |conn| -> anyhow::Result<_> {
+        let user = match db::users::find_by_id(conn, &user_id)? {
+            Some(n) => n,
+            None => return Ok(None),
+        };
+        let roles = db::roles::roles_of_user(conn, user_id)?;
+        Ok(Some(UserInfo::new(user, roles)))
+    })
+    .await?
+    .ok_or_else(|| error::ErrorUnauthorized("Unauthorized"))?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "id": user.id,
+        "created_at": user.created_at,
+        "name": user.name,
+        "roles": user.roles,
+        "permissions": restrictions.permissions,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordForm {
+    old_password: String,
+    new_password: String,
+}
+
+/// Changes the password of the logged in user, every other
+/// session is logged out in case the old one is leaked.
+#[actix_web::post("/api/users/me/password")]
+pub async fn change_password(
+    req: HttpRequest,
+    restrictions: UserRestrictions,
+    pool: DbPool,
+    auth_params: web::Data<AuthParams>,
+    config: web::Data<Config>,
+    form: web::Json<ChangePasswordForm>,
+) -> Result<impl Responder, ApiError> {
+    let limits = &config.limits;
+    create_test_fn!(
+        test_password,
+        limits.max_password_len,
+        limits.min_password_len
+    );
+
+    test_contraints!(
+        test_password,
+        &form.new_password,
+        "password too long",
+        "password too short"
+    );
+
+    let pool = Arc::new(pool);
+    let pool_1 = pool.clone();
+    let pool_2 = pool.clone();
+
+    let user_id = restrictions.user_id;
+    let user = db::run(&pool, move |conn| db::users::find_by_id(conn, &user_id))
+        .await?
+        .ok_or_else(|| error::ErrorUnauthorized("Unauthorized"))?;
+
+    // a stolen session mustn't be able to guess the password
+    let throttle = LoginThrottle::new(&config, &req, &user.name);
+    throttle.check(&pool_1).await?;
+
+    let form = Arc::new(form.into_inner());
+    let form_1 = form.clone();
+    let legacy_salt = auth_params.salt;
+    let password_config = config.password.clone();
+    let verified = web::block(move || {
+        verify_password(
+            &password_config,
+            &legacy_salt,
+            &user.name,
+            &form_1.old_password,
+            &user.password,
+        )
+    })
+    .await?;
+
+    if !verified.is_valid() {
+        let err = error::ErrorForbidden("Old password is incorrect");
+        return Err(throttle.failed(&pool_2, err).await);
+    }
+
+    throttle.succeeded(&pool_2).await?;
+
+    let session_id = restrictions.session_id;
+    let password_config = config.password.clone();
+    let password = web::block(move || hash_password(&password_config, &form.new_password))
+        .await?
+        .map_err(|e| {
+            log::error!("[change_password] failed to hash password: {}", e);
+            ApiError::we_pretend_why_it_does_error()
+        })?;
+
+    let revoked = db::run(&pool_2, move |conn| {
+        db::users::change_password(conn, user_id, &password, session_id)
+    })
+    .await?;
+
+    log::info!(
+        "[change_password] {} changed their password, revoked {} other sessions",
+        user_id,
+        revoked
+    );
+    Ok(HttpResponse::NoContent().finish())
+}
+
+static MIN_QUERY_LEN: usize = 1;
+
+#[derive(Debug, Deserialize)]
+pub struct GetUsersQuery {
+    pub length: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+#[actix_web::get("/api/users")]
+pub async fn get_all(
+    _restrictions: Authorized<ManageUsers>,
+    config: web::Data<Config>,
+    query: web::Query<GetUsersQuery>,
+    pool: DbPool,
+) -> Result<impl Responder, ApiError> {
+    create_test_fn!(as_number -> test_query_len, config.limits.max_query_len, MIN_QUERY_LEN);
+
+    let length = query.length.unwrap_or(10);
+    test_contraints!(
+        test_query_len,
+        length,
+        "Query is too big to handle",
+        "Query is too small to handle"
+    );
+
+    let offset = query.offset.unwrap_or_default();
+    let users = db::run(&pool, move |conn| -> anyhow::Result<Vec<UserInfo>> {
+        let users = db::users::get_all(conn, length, offset)?;
+
+        let ids = users.iter().map(|v| v.id).collect::<Vec<_>>();
+        let mut roles = db::roles::roles_of_users(conn, &ids)?;
+
+        Ok(users
+            .into_iter()
+            .map(|v| {
+                let user_roles = roles.remove(&v.id).unwrap_or_default();
+                UserInfo::new(v, user_roles)
+            })
+            .collect())
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(users))
+}
+
+#[actix_web::get("/api/roles")]
+pub async fn get_roles(
+    _restrictions: Authorized<ManageUsers>,
+    pool: DbPool,
+) -> Result<impl Responder, ApiError> {
+    let roles = db::run(&pool, db::roles::get_all).await?;
+
+    Ok(HttpResponse::Ok().json(roles))
+}
+
+fn unknown_roles(roles: &[String]) -> ApiError {
+    error::ErrorBadRequest(format!("Unknown roles: {}", roles.join(", ")))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateUserForm {
+    username: String,
+    password: String,
+    #[serde(default)]
+    roles: Vec<String>,
+}
+
+/// Registering needs an invite, admins can create users with their roles directly
+#[actix_web::post("/api/users")]
+pub async fn create(
+    restrictions: Authorized<ManageUsers>,
+    pool: DbPool,
+    config: web::Data<Config>,
+    form: web::Json<CreateUserForm>,
+) -> Result<impl Responder, ApiError> {
+    let limits = &config.limits;
+    create_test_fn!(
+        test_password,
+        limits.max_password_len,
+        limits.min_password_len
+    );
+    create_test_fn!(test_username, limits.max_username_len);
+
+    test_contraints!(
+        test_username,
+        &form.username,
+        "username too long",
+        "username too short"
+    );
+    test_contraints!(
+        test_password,
+        &form.password,
+        "password too long",
+        "password too short"
+    );
+
+    let form = Arc::new(form);
+    let form_1 = form.clone();
+
+    let pool = Arc::new(pool);
+    let pool_1 = pool.clone();
+
+    let missing = db::run(&pool, move |conn| {
+        db::roles::find_missing(conn, &form_1.roles)
+    })
+    .await?;
+    if !missing.is_empty() {
+        return Err(unknown_roles(&missing));
+    }
+
+    let form_2 = form.clone();
+    let password_config = config.password.clone();
+    let password = web::block(move || hash_password(&password_config, &form_2.password))
+        .await?
+        .map_err(|e| {
+            log::error!("[create] failed to generate password hash: {}", e);
+            ApiError::we_pretend_why_it_does_error()
+        })?;
+
+    let actor = restrictions.user_id;
+    let user = db::run(&pool_1, move |conn| -> anyhow::Result<Option<UserInfo>> {
+        let created = conn.transaction(|conn| {
+            let user = db::users::insert_with_roles(
+                conn,
+                NewUser {
+                    name: &form.username,
+                    password: &password,
+                },
+                &form.roles,
+            )?;
+
+            let roles = db::roles::roles_of_user(conn, user.id)?;
+            let user = UserInfo::new(user, roles);
+
+            let mut entry =
+                NewAuditEntry::new(Some(actor), AuditAction::CreateUser).after(&user)?;
+            entry.user_id = Some(user.id);
+            db::audit_log::insert(conn, entry)?;
+            Ok(user)
+        });
+
+        match created {
+            Ok(user) => Ok(Some(user)),
+            Err(e) if db::users::is_name_taken(&e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    })
+    .await?
+    .ok_or_else(|| error::ErrorConflict("Username is already taken"))?;
+
+    log::info!(
+        "[create] {} created user {} ({})",
+        restrictions.user_id,
+        user.name,
+        user.id
+    );
+    Ok(HttpResponse::Created().json(user))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetRolesForm {
+    roles: Vec<String>,
+}
+
+/// Replaces every role of the user
+#[actix_web::put("/api/users/{id}/roles")]
+pub async fn set_roles(
+    restrictions: Authorized<ManageUsers>,
+    pool: DbPool,
+    id: web::Path<Uuid>,
+    form: web::Json<SetRolesForm>,
+) -> Result<impl Responder, ApiError> {
+    enum RolesSet {
+        Done(UserInfo),
+        NotFound,
+        Unknown(Vec<String>),
+        LockedOut,
+    }
+
+    let id = id.into_inner();
+    let admin_id = restrictions.user_id;
+    let roles = form.into_inner().roles;
+    let result = db::run(&pool, move |conn| -> anyhow::Result<RolesSet> {
+        let user = match db::users::find_by_id(conn, &id)? {
+            Some(n) => n,
+            None => return Ok(RolesSet::NotFound),
+        };
+
+        let missing = db::roles::find_missing(conn, &roles)?;
+        if !missing.is_empty() {
+            return Ok(RolesSet::Unknown(missing));
+        }
+
+        // otherwise there may be no one left to give it back
+        if id == admin_id
+            && !db::roles::permissions_of_roles(conn, &roles)?.contains(&Permission::ManageUsers)
+        {
+            return Ok(RolesSet::LockedOut);
+        }
+
+        conn.transaction(|conn| {
+            let before = db::roles::roles_of_user(conn, id)?;
+            db::roles::set_user_roles(conn, id, &roles)?;
+            let roles = db::roles::roles_of_user(conn, id)?;
+
+            let mut entry = NewAuditEntry::new(Some(admin_id), AuditAction::SetUserRoles)
+                .before(&json!({ "roles": before }))?
+                .after(&json!({ "roles": roles }))?;
+            entry.user_id = Some(id);
+            db::audit_log::insert(conn, entry)?;
+
+            Ok(RolesSet::Done(UserInfo::new(user, roles)))
+        })
+    })
+    .await?;
+
+    match result {
+        RolesSet::Done(user) => {
+            log::info!(
+                "[set_roles] {} set roles of {} to {:?}",
+                admin_id,
+                user.id,
+                user.roles
+            );
+            Ok(HttpResponse::Ok().json(user))
+        }
+        RolesSet::NotFound => Err(error::ErrorNotFound("User not found")),
+        RolesSet::Unknown(roles) => Err(unknown_roles(&roles)),
+        RolesSet::LockedOut => Err(error::ErrorConflict(
+            "Cannot remove your own permission to manage users",
+        )),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenameForm {
+    username: String,
+}
+
+#[actix_web::put("/api/users/{id}/name")]
+pub async fn rename(
+    restrictions: Authorized<ManageUsers>,
+    pool: DbPool,
+    config: web::Data<Config>,
+    id: web::Path<Uuid>,
+    form: web::Json<RenameForm>,
+) -> Result<impl Responder, ApiError> {
+    create_test_fn!(test_username, config.limits.max_username_len);
+    test_contraints!(
+        test_username,
+        &form.username,
+        "username too long",
+        "username too short"
+    );
+
+    enum Renamed {
+        Done(UserInfo),
+        NotFound,
+        Taken,
+        LegacyPassword,
+    }
+
+    let id = id.into_inner();
+    let actor = restrictions.user_id;
+    let username = form.into_inner().username;
+    let renamed = db::run(&pool, move |conn| -> anyhow::Result<Renamed> {
+        let user = match db::users::find_by_id(conn, &id)? {
+            Some(n) => n,
+            None => return Ok(Renamed::NotFound),
+        };
+
+        // the username is part of legacy hashes, renaming would lock them out
+        if is_legacy_hash(&user.password) {
+            return Ok(Renamed::LegacyPassword);
+        }
+
+        let renamed = conn.transaction(|conn| {
+            let before = json!({ "name": user.name });
+            let user = match db::users::rename(conn, id, &username)? {
+                Some(n) => n,
+                None => return Ok(Renamed::NotFound),
+            };
+
+            let mut entry = NewAuditEntry::new(Some(actor), AuditAction::RenameUser)
+                .before(&before)?
+                .after(&json!({ "name": user.name }))?;
+            entry.user_id = Some(id);
+            db::audit_log::insert(conn, entry)?;
+
+            let roles = db::roles::roles_of_user(conn, id)?;
+            Ok(Renamed::Done(UserInfo::new(user, roles)))
+        });
+
+        match renamed {
+            Err(e) if db::users::is_name_taken(&e) => Ok(Renamed::Taken),
+            renamed => renamed,
+        }
+    })
+    .await?;
+
+    match renamed {
+        Renamed::Done(user) => {
+            log::info!(
+                "[rename] {} renamed {} to {}",
+                restrictions.user_id,
+                user.id,
+                user.name
+            );
+            Ok(HttpResponse::Ok().json(user))
+        }
+        Renamed::NotFound => Err(error::ErrorNotFound("User not found")),
+        Renamed::Taken => Err(error::ErrorConflict("Username is already taken")),
+        Renamed::LegacyPassword => Err(error::ErrorConflict(
+            "The user has to log in once before they can be renamed",
+        )),
+    }
+}
+
+#[actix_web::delete("/api/users/{id}")]
+pub async fn delete(
+    restrictions: Authorized<ManageUsers>,
+    pool: DbPool,
+    id: web::Path<Uuid>,
+) -> Result<impl Responder, ApiError> {
+    let id = id.into_inner();
+    if id == restrictions.user_id {
+        return Err(error::ErrorConflict("Cannot delete yourself"));
+    }
+
+    let actor = restrictions.user_id;
+    let deleted = db::run(&pool, move |conn| -> anyhow::Result<bool> {
+        conn.transaction(|conn| {
+            let user = match db::users::find_by_id(conn, &id)? {
+                Some(n) => n,
+                None => return Ok(false),
+            };
+            let roles = db::roles::roles_of_user(conn, id)?;
+            let before = UserInfo::new(user, roles);
+
+            if !db::users::delete(conn, id)? {
+                return Ok(false);
+            }
+
+            let mut entry =
+                NewAuditEntry::new(Some(actor), AuditAction::DeleteUser).before(&before)?;
+            entry.user_id = Some(id);
+            db::audit_log::insert(conn, entry)?;
+            Ok(true)
+        })
+    })
+    .await?;
+
+    if !deleted {
+        return Err(error::ErrorNotFound("User not found"));
+    }
+
+    log::info!("[delete] {} deleted user {}", restrictions.user_id, id);
+    Ok(HttpResponse::NoContent().finish())
+}
+
 pub fn apply(cfg: &mut ServiceConfig) {
-    cfg.service(login).service(register);
+    cfg.service(login)
+        .service(register)
+        .service(refresh)
+        .service(logout)
+        .service(logout_all)
+        .service(me)
+        .service(change_password)
+        .service(get_all)
+        .service(create)
+        .service(get_roles)
+        .service(set_roles)
+        .service(rename)
+        .service(delete);
 }
@@ -0,0 +1,94 @@
+use anyhow::{anyhow, Result};
+use clap::Subcommand;
+use uuid::Uuid;
+
+use backend_lib::config::Config;
+use backend_lib::db::{self, establish_connection};
+use backend_lib::models::{invite_expiry, NewInvite, MAX_INVITE_EXPIRY_DURATION};
+use backend_lib::utils::token;
+
+#[derive(Debug, Subcommand)]
+pub enum InviteCommand {
+    /// Creates a single use invite and prints its code
+    Create {
+        /// Role to give whoever uses the invite, can be repeated
+        #[arg(long = "role")]
+        roles: Vec<String>,
+        /// How many hours the invite lasts
+        #[arg(long, default_value_t = 24 * 7)]
+        expires_in_hours: i64,
+    },
+    /// Lists invites which are not used yet
+    List,
+    /// Deletes an invite before it's used
+    Delete { id: Uuid },
+}
+
+pub async fn run(config: &Config, command: InviteCommand) -> Result<()> {
+    let mut conn = establish_connection(&config.database)?;
+
+    match command {
+        InviteCommand::Create {
+            roles,
+            expires_in_hours,
+        } => {
+            let expires_in = expires_in_hours * 60 * 60;
+            if expires_in <= 0 || expires_in > MAX_INVITE_EXPIRY_DURATION {
+                return Err(anyhow!(
+                    "invites must expire within 1 to {} hours",
+                    MAX_INVITE_EXPIRY_DURATION / 60 / 60
+                ));
+            }
+
+            let missing = db::roles::find_missing(&mut conn, &roles)?;
+            if !missing.is_empty() {
+                return Err(anyhow!("unknown roles: {}", missing.join(", ")));
+            }
+
+            let code = token::generate();
+            let now = chrono::Utc::now().naive_utc();
+            let invite = db::invites::insert(
+                &mut conn,
+                NewInvite {
+                    created_by: None,
+                    expires_at: invite_expiry(now, expires_in),
+                    code_hash: &token::hash(&code),
+                },
+                &roles,
+            )?;
+
+            log::info!(
+                "Created invite {} (roles = {:?}; expires at {})",
+                invite.id,
+                roles,
+                invite.expires_at
+            );
+            println!("{}", code);
+        }
+        InviteCommand::List => {
+            let now = chrono::Utc::now().naive_utc();
+            for info in db::invites::get_unused(&mut conn)? {
+                let expired = if info.invite.expires_at <= now {
+                    " (expired)"
+                } else {
+                    ""
+                };
+                println!(
+                    "{}: roles = [{}]; expires at {}{}",
+                    info.invite.id,
+                    info.roles.join(", "),
+                    info.invite.expires_at,
+                    expired
+                );
+            }
+        }
+        InviteCommand::Delete { id } => {
+            if db::invites::delete_unused(&mut conn, id)?.is_none() {
+                return Err(anyhow!("invite {} does not exist or is already used", id));
+            }
+            log::info!("Deleted invite {}", id);
+        }
+    }
+
+    Ok(())
+}
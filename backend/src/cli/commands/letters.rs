@@ -0,0 +1,137 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use clap::Subcommand;
+use diesel::Connection;
+
+use backend_lib::config::Config;
+use backend_lib::db::{self, establish_connection};
+use backend_lib::models;
+use backend_lib::utils::letter::{decrypt_message, encrypt_message, key_id};
+
+#[derive(Debug, Subcommand)]
+pub enum LettersCommand {
+    /// Re-encrypts secret letters that are not encrypted with the active key
+    Reencrypt {
+        /// Amount of letters to update in a single transaction
+        #[arg(long, default_value_t = 100)]
+        batch_size: usize,
+    },
+    /// Exports every letter into a JSON file
+    Export {
+        /// Where to write the letters into
+        #[arg(long, short)]
+        output: PathBuf,
+        /// Decrypts secret letters before exporting them
+        #[arg(long)]
+        decrypt: bool,
+    },
+}
+
+pub async fn run(config: &Config, command: LettersCommand) -> Result<()> {
+    match command {
+        LettersCommand::Reencrypt { batch_size } => reencrypt(config, batch_size.max(1)).await,
+        LettersCommand::Export { output, decrypt } => export(config, output, decrypt).await,
+    }
+}
+
+static EXPORT_BATCH_SIZE: usize = 100;
+
+async fn export(config: &Config, output: PathBuf, decrypt: bool) -> Result<()> {
+    let auth = decrypt.then(|| config.auth()).transpose()?;
+    let keys = auth.as_ref().map(|v| &v.secret_keys);
+    let mut conn = establish_connection(&config.database)?;
+
+    let mut letters = Vec::new();
+    loop {
+        let after = letters.last().map(|v: &models::Letter| v.id);
+        let batch = db::letters::get_batch(&mut conn, after, EXPORT_BATCH_SIZE, false)?;
+        if batch.is_empty() {
+            break;
+        }
+        letters.extend(batch);
+    }
+
+    if let Some(keys) = keys {
+        for letter in letters.iter_mut().filter(|v| v.secret) {
+            letter.message = decrypt_message(keys, &letter.author, &letter.message)
+                .await
+                .with_context(|| format!("failed to decrypt letter {}", letter.id))?;
+        }
+    }
+
+    let file =
+        File::create(&output).with_context(|| format!("failed to create {}", output.display()))?;
+
+    serde_json::to_writer_pretty(BufWriter::new(file), &letters)
+        .with_context(|| "failed to write letters")?;
+
+    log::info!("Exported {} letters to {}", letters.len(), output.display());
+    Ok(())
+}
+
+async fn reencrypt(config: &Config, batch_size: usize) -> Result<()> {
+    let auth = config.auth()?;
+    let keys = &auth.secret_keys;
+    let mut conn = establish_connection(&config.database)?;
+
+    log::info!(
+        "Re-encrypting secret letters with key {:?} (batch size = {})",
+        keys.active.id,
+        batch_size
+    );
+
+    let mut after = None;
+    let mut updated = 0;
+    let mut failed = 0;
+
+    loop {
+        let letters = db::letters::get_batch(&mut conn, after, batch_size, true)?;
+        let last = match letters.last() {
+            Some(n) => n.id,
+            None => break,
+        };
+
+        let mut changes = Vec::new();
+        for letter in letters {
+            if key_id(&letter.message) == Some(keys.active.id.as_str()) {
+                continue;
+            }
+
+            let message = match decrypt_message(keys, &letter.author, &letter.message).await {
+                Ok(n) => n,
+                Err(err) => {
+                    log::error!("Failed to decrypt letter {}: {}", letter.id, err);
+                    failed += 1;
+                    continue;
+                }
+            };
+
+            let message = encrypt_message(keys, &letter.author, &message).await?;
+            changes.push((letter.id, message));
+        }
+
+        conn.transaction::<_, anyhow::Error, _>(|conn| {
+            for (id, message) in changes.iter() {
+                db::letters::update_message(conn, *id, message)?;
+            }
+            Ok(())
+        })?;
+
+        updated += changes.len();
+        after = Some(last);
+        log::info!("Re-encrypted {} letters so far", updated);
+    }
+
+    log::info!("Done re-encrypting {} letters", updated);
+    if failed > 0 {
+        Err(anyhow!(
+            "{} letters cannot be decrypted with any of the configured keys",
+            failed
+        ))
+    } else {
+        Ok(())
+    }
+}
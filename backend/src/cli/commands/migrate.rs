@@ -0,0 +1,18 @@
+use anyhow::Result;
+use backend_lib::config::Config;
+use backend_lib::db::run_migrations;
+use diesel::{Connection, PgConnection};
+
+pub async fn run(config: &Config) -> Result<()> {
+    let mut conn = PgConnection::establish(&config.database.url)?;
+
+    let versions = run_migrations(&mut conn)?;
+    if versions.is_empty() {
+        log::info!("Database is already up to date");
+    }
+    for version in versions {
+        log::info!("Applied migration {}", version);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,7 @@
+pub mod invites;
+pub mod letters;
+pub mod migrate;
+pub mod roles;
+pub mod serve;
+pub mod submissions;
+pub mod users;
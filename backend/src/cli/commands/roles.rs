@@ -0,0 +1,152 @@
+use anyhow::{anyhow, Result};
+use clap::Subcommand;
+
+use backend_lib::config::Config;
+use backend_lib::db::{self, establish_connection};
+use backend_lib::models::{AuditAction, NewAuditEntry, Permission, RoleInfo};
+
+use diesel::{Connection, PgConnection};
+use serde_json::json;
+
+#[derive(Debug, Subcommand)]
+pub enum RoleCommand {
+    /// Lists every role and their permissions
+    List,
+    /// Creates a new role
+    Create {
+        name: String,
+        /// Permission to give the role, can be repeated
+        #[arg(long = "permission", value_parser = parse_permission)]
+        permissions: Vec<Permission>,
+        /// Only gives its permissions to users with two-factor authentication
+        #[arg(long)]
+        require_2fa: bool,
+    },
+    /// Requires two-factor authentication for the permissions of a role
+    #[command(name = "require-2fa")]
+    Require2fa {
+        name: String,
+        /// Stops requiring it instead
+        #[arg(long)]
+        off: bool,
+    },
+    /// Deletes a role, taking it away from its users
+    Delete { name: String },
+}
+
+// same as the column in `roles`
+static MAX_ROLE_NAME_LEN: usize = 50;
+
+fn parse_permission(value: &str) -> Result<Permission> {
+    value.parse().map_err(|_| {
+        let known = Permission::ALL.map(|v| v.as_str()).join(", ");
+        anyhow!("unknown permission, expected one of: {}", known)
+    })
+}
+
+fn find_role(conn: &mut PgConnection, name: &str) -> Result<Option<RoleInfo>> {
+    Ok(db::roles::get_all(conn)?
+        .into_iter()
+        .find(|v| v.name == name))
+}
+
+pub async fn run(config: &Config, command: RoleCommand) -> Result<()> {
+    let mut conn = establish_connection(&config.database)?;
+
+    match command {
+        RoleCommand::List => {
+            for role in db::roles::get_all(&mut conn)? {
+                let permissions = role
+                    .permissions
+                    .iter()
+                    .map(|v| v.as_str())
+                    .collect::<Vec<_>>();
+                let requires_2fa = if role.requires_2fa {
+                    " (requires 2FA)"
+                } else {
+                    ""
+                };
+                println!("{}{}: {}", role.name, requires_2fa, permissions.join(", "));
+            }
+        }
+        RoleCommand::Create {
+            name,
+            permissions,
+            require_2fa,
+        } => {
+            if name.is_empty() || name.len() > MAX_ROLE_NAME_LEN {
+                return Err(anyhow!(
+                    "role name must be 1 to {} characters long",
+                    MAX_ROLE_NAME_LEN
+                ));
+            }
+            if db::roles::find_missing(&mut conn, std::slice::from_ref(&name))?.is_empty() {
+                return Err(anyhow!("role {:?} already exists", name));
+            }
+
+            conn.transaction(|conn| -> Result<_> {
+                db::roles::insert(conn, &name, &permissions, require_2fa)?;
+
+                let entry = NewAuditEntry::new(None, AuditAction::CreateRole).after(&json!({
+                    "name": name,
+                    "permissions": permissions,
+                    "requires_2fa": require_2fa,
+                }))?;
+                db::audit_log::insert(conn, entry)?;
+                Ok(())
+            })?;
+            let permissions = permissions.iter().map(|v| v.as_str()).collect::<Vec<_>>();
+            log::info!("Created role {} ({})", name, permissions.join(", "));
+        }
+        RoleCommand::Require2fa { name, off } => {
+            let updated = conn.transaction(|conn| -> Result<_> {
+                let before = match find_role(conn, &name)? {
+                    Some(n) => n,
+                    None => return Ok(false),
+                };
+                if !db::roles::set_requires_2fa(conn, &name, !off)? {
+                    return Ok(false);
+                }
+
+                let entry = NewAuditEntry::new(None, AuditAction::SetRoleRequires2fa)
+                    .before(&json!({ "name": name, "requires_2fa": before.requires_2fa }))?
+                    .after(&json!({ "name": name, "requires_2fa": !off }))?;
+                db::audit_log::insert(conn, entry)?;
+                Ok(true)
+            })?;
+            if !updated {
+                return Err(anyhow!("role {:?} does not exist", name));
+            }
+            log::info!(
+                "Role {} {} two-factor authentication",
+                name,
+                if off {
+                    "no longer requires"
+                } else {
+                    "requires"
+                }
+            );
+        }
+        RoleCommand::Delete { name } => {
+            let deleted = conn.transaction(|conn| -> Result<_> {
+                let before = match find_role(conn, &name)? {
+                    Some(n) => n,
+                    None => return Ok(false),
+                };
+                if !db::roles::delete(conn, &name)? {
+                    return Ok(false);
+                }
+
+                let entry = NewAuditEntry::new(None, AuditAction::DeleteRole).before(&before)?;
+                db::audit_log::insert(conn, entry)?;
+                Ok(true)
+            })?;
+            if !deleted {
+                return Err(anyhow!("role {:?} does not exist", name));
+            }
+            log::info!("Deleted role {}", name);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,155 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::dev::Service;
+use actix_web::{guard, middleware};
+use actix_web::{web, App, HttpServer};
+
+use anyhow::{anyhow, Context, Result};
+
+use backend_lib::config::Config;
+use backend_lib::db::establish_db_pool;
+
+use crate::ratelimit::{RateLimit, RateLimits};
+use crate::tls::{self, Certificates};
+use crate::{api, csrf, frontend, listen};
+
+static RATE_LIMITS_CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+pub async fn run(config: Config) -> Result<()> {
+    let auth = config.auth()?;
+    let rate_limits = Arc::new(RateLimits::new(&config, &auth));
+    {
+        let rate_limits = rate_limits.clone();
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(RATE_LIMITS_CLEANUP_INTERVAL);
+            loop {
+                interval.tick().await;
+                rate_limits.retain_recent();
+            }
+        });
+    }
+
+    let db = establish_db_pool(&config.database).await?;
+
+    let static_dir = config.server.static_dir.clone();
+    let serve_frontend = static_dir.is_dir();
+    if serve_frontend {
+        log::info!("Serving frontend files from {}", static_dir.display());
+    } else {
+        log::warn!(
+            "Frontend directory {} does not exist, only serving the API",
+            static_dir.display()
+        );
+    }
+
+    log::info!(
+        "Launching actix-web server (hosts = {:?}; port = {})",
+        config.server.hosts,
+        config.server.port
+    );
+
+    let server_config = config.server.clone();
+    let certificates = config.tls.as_ref().map(Certificates::load).transpose()?;
+    let redirect_port = config.tls.as_ref().and_then(|v| v.redirect_port);
+    let auth_params = web::Data::new(auth);
+    let config = web::Data::new(config);
+
+    let mut server = HttpServer::new(move || {
+        let csrf_config = config.clone();
+        let rate_limits = rate_limits.clone();
+
+        App::new()
+            .service(
+                web::scope("")
+                    .guard(guard::fn_guard(api::is_api_request))
+                    .configure(api::apply)
+                    .default_service(web::to(api::not_found))
+                    .wrap_fn(move |req, srv| {
+                        let res = csrf::check(&req, &csrf_config.csrf).map(|_| srv.call(req));
+                        async move { res?.await }
+                    })
+                    // static files should not count towards the ratelimit
+                    .wrap(RateLimit(rate_limits)),
+            )
+            .configure(|cfg| {
+                if serve_frontend {
+                    frontend::apply(cfg, &static_dir);
+                }
+            })
+            // middleware
+            .wrap(middleware::Logger::default())
+            .app_data(db.clone())
+            .app_data(config.clone())
+            .app_data(auth_params.clone())
+    });
+
+    for host in server_config.hosts.iter() {
+        for listener in listen::bind(host, server_config.port)? {
+            server = match certificates.as_ref() {
+                Some(certificates) => server.listen_openssl(listener, certificates.acceptor()?),
+                None => server.listen(listener),
+            }
+            .with_context(|| format!("failed to listen on {}:{}", host, server_config.port))?;
+        }
+    }
+
+    // before binding the Unix socket, it shows up as a dummy address in there
+    let scheme = if certificates.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    for addr in server.addrs() {
+        log::info!("Listening at {}://{}", scheme, addr);
+    }
+
+    if let Some(path) = server_config.unix_socket.as_deref() {
+        #[cfg(unix)]
+        {
+            remove_stale_socket(path)?;
+            server = server
+                .bind_uds(path)
+                .with_context(|| format!("failed to bind {}", path.display()))?;
+
+            log::info!("Listening at unix:{}", path.display());
+        }
+
+        #[cfg(not(unix))]
+        return Err(anyhow!(
+            "cannot listen on {}, Unix sockets are not supported",
+            path.display()
+        ));
+    }
+
+    if let Some(certificates) = certificates {
+        certificates.reload_on_sighup()?;
+    }
+
+    let server = server.run();
+    match redirect_port {
+        Some(redirect_port) => {
+            let redirect =
+                tls::redirect_server(&server_config.hosts, redirect_port, server_config.port)?;
+            tokio::try_join!(server, redirect).with_context(|| "server error")?;
+            Ok(())
+        }
+        None => server.await.with_context(|| "server error"),
+    }
+}
+
+/// Removes the socket left behind if the server didn't shut down cleanly
+#[cfg(unix)]
+fn remove_stale_socket(path: &std::path::Path) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(anyhow!("{} exists and is not a socket", path.display()));
+        }
+        log::warn!("Removing stale Unix socket {}", path.display());
+        std::fs::remove_file(path)
+            .with_context(|| format!("failed to remove {}", path.display()))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
+use clap::Subcommand;
+
+use backend_lib::config::Config;
+use backend_lib::db::{self, establish_connection};
+use backend_lib::models::{AuditAction, NewAuditEntry, UpdateState};
+use diesel::Connection;
+
+#[derive(Debug, Subcommand)]
+pub enum SubmissionsCommand {
+    /// Starts accepting new letters
+    Open {
+        /// When to start accepting letters (in UTC, e.g. 2022-10-01T08:00:00)
+        #[arg(long)]
+        opens_at: Option<NaiveDateTime>,
+        /// When to stop accepting letters (in UTC)
+        #[arg(long)]
+        closes_at: Option<NaiveDateTime>,
+        /// Shown to visitors once submissions are closed
+        #[arg(long)]
+        message: Option<String>,
+    },
+    /// Stops accepting new letters
+    Close {
+        /// Shown to visitors while submissions are closed
+        #[arg(long)]
+        message: Option<String>,
+    },
+    /// Makes new letters wait for a moderator's approval before being public
+    Premoderation {
+        #[arg(action = clap::ArgAction::Set)]
+        enabled: bool,
+    },
+    /// Shows whether new letters are accepted
+    Status,
+}
+
+pub async fn run(config: &Config, command: SubmissionsCommand) -> Result<()> {
+    let new_state = match command {
+        SubmissionsCommand::Open {
+            opens_at,
+            closes_at,
+            message,
+        } => UpdateState {
+            available: true,
+            opens_at,
+            closes_at,
+            closed_message: message,
+        },
+        SubmissionsCommand::Close { message } => UpdateState {
+            available: false,
+            opens_at: None,
+            closes_at: None,
+            closed_message: message,
+        },
+        SubmissionsCommand::Premoderation { enabled } => {
+            return premoderation(config, enabled).await
+        }
+        SubmissionsCommand::Status => return status(config).await,
+    };
+
+    let now = chrono::Utc::now().naive_utc();
+    new_state.validate(now).map_err(|e| anyhow!(e))?;
+
+    let mut conn = establish_connection(&config.database)?;
+
+    let state = conn.transaction(|conn| -> Result<_> {
+        let before = db::state::get(conn)?;
+        let state = db::state::set_available(conn, new_state)?;
+
+        let entry = NewAuditEntry::new(None, AuditAction::SetAvailability)
+            .before(&before)?
+            .after(&state)?;
+        db::audit_log::insert(conn, entry)?;
+        Ok(state)
+    })?;
+    log::info!(
+        "Submissions are now {}",
+        if state.is_available_at(now) {
+            "open"
+        } else {
+            "closed"
+        }
+    );
+
+    Ok(())
+}
+
+async fn premoderation(config: &Config, enabled: bool) -> Result<()> {
+    let mut conn = establish_connection(&config.database)?;
+
+    let state = conn.transaction(|conn| -> Result<_> {
+        let before = db::state::get(conn)?;
+        let state = db::state::set_premoderation(conn, enabled)?;
+
+        let entry = NewAuditEntry::new(None, AuditAction::SetPremoderation)
+            .before(&before)?
+            .after(&state)?;
+        db::audit_log::insert(conn, entry)?;
+        Ok(state)
+    })?;
+    log::info!(
+        "Pre-moderation is now {}",
+        if state.premoderation {
+            "enabled"
+        } else {
+            "disabled"
+        }
+    );
+
+    Ok(())
+}
+
+async fn status(config: &Config) -> Result<()> {
+    let mut conn = establish_connection(&config.database)?;
+
+    let now = chrono::Utc::now().naive_utc();
+    match db::state::get(&mut conn)? {
+        Some(state) => {
+            log::info!(
+                "Submissions are {} (opens_at = {:?}; closes_at = {:?}; premoderation = {})",
+                if state.is_available_at(now) {
+                    "open"
+                } else {
+                    "closed"
+                },
+                state.opens_at,
+                state.closes_at,
+                state.premoderation
+            );
+        }
+        None => log::info!("Submissions are closed (state is not set up)"),
+    }
+
+    Ok(())
+}
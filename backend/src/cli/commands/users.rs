@@ -0,0 +1,148 @@
+use anyhow::{anyhow, Result};
+use clap::Subcommand;
+use diesel::{Connection, PgConnection};
+use serde_json::json;
+
+use backend_lib::config::Config;
+use backend_lib::db::{self, establish_connection};
+use backend_lib::models::{AuditAction, NewAuditEntry, NewUser};
+use backend_lib::utils::password::hash_password;
+
+#[derive(Debug, Subcommand)]
+pub enum UserCommand {
+    /// Creates a new user, the password will be prompted
+    Create {
+        name: String,
+        /// Role to give the user, can be repeated (see `role list`)
+        #[arg(long = "role")]
+        roles: Vec<String>,
+        /// Reads the password from stdin instead of prompting it
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// Replaces every role of a user
+    Roles { name: String, roles: Vec<String> },
+    /// Revokes every session of a user, logging them out everywhere
+    Logout { name: String },
+}
+
+pub async fn run(config: &Config, command: UserCommand) -> Result<()> {
+    match command {
+        UserCommand::Create {
+            name,
+            roles,
+            password_stdin,
+        } => create(config, name, roles, password_stdin).await,
+        UserCommand::Roles { name, roles } => set_roles(config, name, roles).await,
+        UserCommand::Logout { name } => logout(config, name).await,
+    }
+}
+
+fn read_password(from_stdin: bool) -> Result<String> {
+    if from_stdin {
+        let mut password = String::new();
+        std::io::stdin().read_line(&mut password)?;
+        return Ok(password.trim_end_matches(&['\r', '\n'][..]).to_string());
+    }
+
+    let password = rpassword::prompt_password("Password: ")?;
+    if rpassword::prompt_password("Confirm password: ")? != password {
+        return Err(anyhow!("passwords do not match"));
+    }
+
+    Ok(password)
+}
+
+async fn create(
+    config: &Config,
+    name: String,
+    roles: Vec<String>,
+    password_stdin: bool,
+) -> Result<()> {
+    let limits = &config.limits;
+    if name.is_empty() || name.len() > limits.max_username_len {
+        return Err(anyhow!(
+            "username must be 1 to {} characters long",
+            limits.max_username_len
+        ));
+    }
+
+    let mut conn = establish_connection(&config.database)?;
+
+    if db::users::find_by_username(&mut conn, &name)?.is_some() {
+        return Err(anyhow!("user {:?} already exists", name));
+    }
+    ensure_roles_exist(&mut conn, &roles)?;
+
+    let password = read_password(password_stdin)?;
+    if password.len() < limits.min_password_len || password.len() > limits.max_password_len {
+        return Err(anyhow!(
+            "password must be {} to {} characters long",
+            limits.min_password_len,
+            limits.max_password_len
+        ));
+    }
+
+    let password = hash_password(&config.password, &password)?;
+    let user = db::users::insert_with_roles(
+        &mut conn,
+        NewUser {
+            name: &name,
+            password: &password,
+        },
+        &roles,
+    )
+    .map_err(|e| match db::users::is_name_taken(&e) {
+        true => anyhow!("user {:?} already exists", name),
+        false => e,
+    })?;
+
+    log::info!(
+        "Created user {} (id = {}; roles = {:?})",
+        user.name,
+        user.id,
+        roles
+    );
+    Ok(())
+}
+
+fn ensure_roles_exist(conn: &mut PgConnection, roles: &[String]) -> Result<()> {
+    let missing = db::roles::find_missing(conn, roles)?;
+    if !missing.is_empty() {
+        return Err(anyhow!("unknown roles: {}", missing.join(", ")));
+    }
+    Ok(())
+}
+
+async fn set_roles(config: &Config, name: String, roles: Vec<String>) -> Result<()> {
+    let mut conn = establish_connection(&config.database)?;
+
+    let user = db::users::find_by_username(&mut conn, &name)?
+        .ok_or_else(|| anyhow!("user {:?} does not exist", name))?;
+
+    ensure_roles_exist(&mut conn, &roles)?;
+    conn.transaction(|conn| -> Result<()> {
+        let before = db::roles::roles_of_user(conn, user.id)?;
+        db::roles::set_user_roles(conn, user.id, &roles)?;
+
+        let mut entry = NewAuditEntry::new(None, AuditAction::SetUserRoles)
+            .before(&json!({ "roles": before }))?
+            .after(&json!({ "roles": roles }))?;
+        entry.user_id = Some(user.id);
+        db::audit_log::insert(conn, entry)
+    })?;
+
+    log::info!("Roles of {} are now {:?}", user.name, roles);
+    Ok(())
+}
+
+async fn logout(config: &Config, name: String) -> Result<()> {
+    let mut conn = establish_connection(&config.database)?;
+
+    let user = db::users::find_by_username(&mut conn, &name)?
+        .ok_or_else(|| anyhow!("user {:?} does not exist", name))?;
+
+    let revoked = db::sessions::revoke_all(&mut conn, user.id, chrono::Utc::now().naive_utc())?;
+    log::info!("Revoked {} sessions of {}", revoked, user.name);
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use actix_web::dev::ServiceRequest;
+use actix_web::http::{header, Method};
+
+use backend_lib::config::CsrfConfig;
+use backend_lib::reqs::{bearer_token, REFRESH_TOKEN_COOKIE, TOKEN_COOKIE};
+use backend_lib::resp::error;
+
+static FORBIDDEN_ORIGIN: &str = "Cross-site requests are not allowed";
+
+fn is_safe_method(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    )
+}
+
+/// Other sites can't add an `Authorization` header without CORS (which
+/// we don't allow) so only requests relying on cookies can be forged.
+fn uses_session_cookies(req: &ServiceRequest) -> bool {
+    bearer_token(req.headers()).is_none()
+        && (req.cookie(TOKEN_COOKIE).is_some() || req.cookie(REFRESH_TOKEN_COOKIE).is_some())
+}
+
+/// Gets `scheme://authority` of the site sending the request, browsers
+/// may only send `Referer` on older versions or same-origin requests.
+fn request_origin(req: &ServiceRequest) -> Option<String> {
+    let headers = req.headers();
+    if let Some(origin) = headers.get(header::ORIGIN) {
+        return origin.to_str().ok().map(|v| v.to_ascii_lowercase());
+    }
+
+    let referer = headers.get(header::REFERER)?.to_str().ok()?;
+    let (scheme, rest) = referer.split_once("://")?;
+    let authority = rest.split('/').next().unwrap_or_default();
+
+    Some(format!("{}://{}", scheme, authority).to_ascii_lowercase())
+}
+
+fn is_trusted(req: &ServiceRequest, origin: &str, config: &CsrfConfig) -> bool {
+    if config.trusted_origins.iter().any(|v| v == origin) {
+        return true;
+    }
+
+    // only the host is compared since TLS may be terminated by a reverse proxy
+    let host = req.connection_info().host().to_ascii_lowercase();
+    origin
+        .split_once("://")
+        .map(|(_, authority)| authority == host)
+        .unwrap_or_default()
+}
+
+/// Rejects cookie authenticated requests changing anything
+/// unless they come from the same origin or a trusted one.
+pub fn check(req: &ServiceRequest, config: &CsrfConfig) -> Result<(), actix_web::Error> {
+    if is_safe_method(req.method()) || !uses_session_cookies(req) {
+        return Ok(());
+    }
+
+    match request_origin(req) {
+        Some(origin) if is_trusted(req, &origin, config) => Ok(()),
+        origin => {
+            log::warn!(
+                "[csrf] rejected {} {} from origin {:?}",
+                req.method(),
+                req.path(),
+                origin
+            );
+            Err(error::ErrorForbidden(FORBIDDEN_ORIGIN).into())
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::path::{Path, PathBuf};
+
+use actix_files::{Files, NamedFile};
+
+use actix_web::dev::{fn_service, Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::Method;
+use actix_web::web::{self, ServiceConfig};
+use actix_web::HttpRequest;
+
+use backend_lib::resp::error;
+
+static INDEX_FILE: &str = "index.html";
+
+// create-react-app puts content hashed files in there, so browsers
+// can keep them forever without asking again
+static IMMUTABLE_PREFIX: &str = "/static/";
+
+static IMMUTABLE_CACHE: &str = "public, max-age=31536000, immutable";
+static REVALIDATE_CACHE: &str = "no-cache";
+
+/// Whether the request is meant for react-router (`/dashboard`, `/login`, ...)
+/// rather than a missing asset like `/static/js/main.js`.
+fn is_client_route(req: &HttpRequest) -> bool {
+    let method = req.method();
+    if method != Method::GET && method != Method::HEAD {
+        return false;
+    }
+
+    let last_segment = req.path().rsplit('/').next().unwrap_or_default();
+    !last_segment.contains('.')
+}
+
+async fn fallback(
+    index: PathBuf,
+    req: ServiceRequest,
+) -> Result<ServiceResponse, actix_web::Error> {
+    let (req, _) = req.into_parts();
+    if !is_client_route(&req) {
+        return Err(error::ErrorNotFound("Not found").into());
+    }
+
+    log::debug!("[frontend] serving {} for {}", INDEX_FILE, req.path());
+    let file = NamedFile::open_async(&index).await?;
+    let res = file.into_response(&req);
+
+    Ok(ServiceResponse::new(req, res))
+}
+
+/// Serves the compiled frontend from `root` with the `index.html`
+/// fallback for client-side routes.
+///
+/// It has to be configured after the API since it catches every path.
+pub fn apply(cfg: &mut ServiceConfig, root: &Path) {
+    let index = root.join(INDEX_FILE);
+    let files = Files::new("/", root)
+        .index_file(INDEX_FILE)
+        .default_handler(fn_service(move |req| fallback(index.clone(), req)));
+
+    cfg.service(
+        web::scope("")
+            .wrap_fn(|req, srv| {
+                let immutable = req.path().starts_with(IMMUTABLE_PREFIX);
+                let res = srv.call(req);
+                async move {
+                    let mut res = res.await?;
+                    if res.status().is_success() {
+                        let value = if immutable {
+                            IMMUTABLE_CACHE
+                        } else {
+                            REVALIDATE_CACHE
+                        };
+                        res.headers_mut()
+                            .insert(header::CACHE_CONTROL, HeaderValue::from_static(value));
+                    }
+                    Ok(res)
+                }
+            })
+            .service(files),
+    );
+}
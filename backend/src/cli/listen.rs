@@ -0,0 +1,34 @@
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+
+use anyhow::{Context, Result};
+use socket2::{Domain, Protocol, Socket, Type};
+
+// same as actix-web's default
+static BACKLOG: i32 = 1024;
+
+fn listener(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+
+    // otherwise `::` takes IPv4 as well on Linux and
+    // binding `0.0.0.0` on the same port fails after it
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+
+    socket.bind(&addr.into())?;
+    socket.listen(BACKLOG)?;
+    Ok(socket.into())
+}
+
+/// Binds every address the host resolves to, so `::` and
+/// `0.0.0.0` can listen on the same port together.
+pub fn bind(host: &str, port: u16) -> Result<Vec<TcpListener>> {
+    let addrs = (host, port)
+        .to_socket_addrs()
+        .with_context(|| format!("failed to resolve {}:{}", host, port))?;
+
+    addrs
+        .map(|addr| listener(addr).with_context(|| format!("failed to bind {}", addr)))
+        .collect()
+}
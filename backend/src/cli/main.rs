@@ -1,83 +1,75 @@
-use actix_governor::{Governor, GovernorConfigBuilder};
+use std::path::PathBuf;
 
-use actix_web::middleware;
-use actix_web::{web, App, HttpServer};
+use anyhow::Result;
+use clap::{Parser, Subcommand};
 
-use anyhow::{Context, Result};
-
-use backend_lib::config::AuthParams;
-use backend_lib::config::{
-    encoding_key, reg_key, salt_key, secret_aes_key, server_address, server_port,
-};
-
-use backend_lib::db::establish_db_pool;
-use backend_lib::{logger, vec_to_sized};
+use backend_lib::config::Config;
+use backend_lib::logger;
 
 mod api;
+mod commands;
+mod csrf;
+mod frontend;
+mod listen;
+mod ratelimit;
+mod tls;
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    /// TOML configuration file, environment variables take precedence over it
+    #[arg(long, short, global = true, env = "CONFIG_FILE")]
+    config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Runs the web server (default)
+    Serve,
+    /// Applies pending database migrations
+    Migrate,
+    /// Manages users
+    #[command(subcommand)]
+    User(commands::users::UserCommand),
+    /// Manages roles and their permissions
+    #[command(subcommand)]
+    Role(commands::roles::RoleCommand),
+    /// Manages invites needed to register
+    #[command(subcommand)]
+    Invite(commands::invites::InviteCommand),
+    /// Opens or closes letter submissions
+    #[command(subcommand)]
+    Submissions(commands::submissions::SubmissionsCommand),
+    /// Manages letters
+    #[command(subcommand)]
+    Letters(commands::letters::LettersCommand),
+}
 
 /// Preloads any prequisities to the program before it runs
-/// the app web server (it includes logging)
-fn preload() -> Result<()> {
+/// the app web server (it includes logging and configuration)
+fn preload(cli: &Cli) -> Result<Config> {
     logger::init_logger()?;
-
-    log::debug!("Loading .env file (optional)");
-    dotenv::dotenv().ok();
-    Ok(())
+    Config::load(cli.config.as_deref())
 }
 
 #[actix_web::main]
 async fn main() -> Result<()> {
-    preload()?;
-
-    let governor_conf = GovernorConfigBuilder::default()
-        .per_second(1)
-        .burst_size(3)
-        .finish()
-        .with_context(|| "failed to setup ratelimiter")?;
-
-    let address = server_address()?;
-    let port = server_port()?;
-
-    let salt = salt_key()?;
-    let encode_key = encoding_key()?;
-    let register_key = reg_key()?;
-
-    let secret_key = secret_aes_key()?;
-
-    let db = establish_db_pool().await?;
-
-    log::info!(
-        "Launching actix-web server (address = {}; port = {})",
-        address,
-        port
-    );
-
-    let server = HttpServer::new(move || {
-        let mut salt_sized = [0u8; 16];
-        vec_to_sized!(salt, salt_sized);
-
-        let mut reg_sized = [0u8; 16];
-        vec_to_sized!(register_key, reg_sized);
-
-        let mut secret_key_sized = [0u8; 16];
-        vec_to_sized!(secret_key, secret_key_sized);
-
-        App::new()
-            .configure(api::apply)
-            // middleware
-            .wrap(Governor::new(&governor_conf))
-            .wrap(middleware::Logger::default())
-            .app_data(db.clone())
-            .app_data(web::Data::new(AuthParams {
-                salt: salt_sized,
-                token: encode_key.clone(),
-                reg: reg_sized,
-
-                secret_key: secret_key_sized,
-            }))
-    })
-    .bind((address, port))?;
+    // `.env` has to be loaded before parsing since CONFIG_FILE can be in there
+    dotenv::dotenv().ok();
 
-    log::info!("Listening at http://{}:{}", address, port);
-    server.run().await.with_context(|| "server error")
+    let cli = Cli::parse();
+    let config = preload(&cli)?;
+
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => commands::serve::run(config).await,
+        Command::Migrate => commands::migrate::run(&config).await,
+        Command::User(command) => commands::users::run(&config, command).await,
+        Command::Role(command) => commands::roles::run(&config, command).await,
+        Command::Invite(command) => commands::invites::run(&config, command).await,
+        Command::Submissions(command) => commands::submissions::run(&config, command).await,
+        Command::Letters(command) => commands::letters::run(&config, command).await,
+    }
 }
@@ -0,0 +1,558 @@
+use std::collections::HashMap;
+use std::future::{ready, Future, Ready};
+use std::net::{IpAddr, SocketAddr};
+use std::num::NonZeroU32;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderMap};
+use actix_web::http::Method;
+use actix_web::HttpRequest;
+
+use backend_lib::config::{
+    AuthParams, Config, ForwardedHeader, LoginThrottleConfig, RateLimitPolicy, ServerConfig,
+};
+use backend_lib::db::{self, DbPool};
+use backend_lib::models::{ip_throttle_key, user_throttle_key, UserToken};
+use backend_lib::reqs::{bearer_token, TOKEN_COOKIE};
+use backend_lib::resp::error::{self, ApiError};
+
+use chrono::NaiveDateTime;
+
+use governor::clock::{Clock, DefaultClock};
+use governor::state::keyed::DefaultKeyedStateStore;
+use governor::{Quota, RateLimiter};
+
+use uuid::Uuid;
+
+/// How long whether a session is staff is remembered for
+static STAFF_CACHE_DURATION: Duration = Duration::from_secs(30);
+
+/// Parses an address of `Forwarded`/`X-Forwarded-For`, which may have a port
+fn parse_forwarded_ip(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    if let Some(rest) = value.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok();
+    }
+
+    value
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|v| v.ip()))
+}
+
+/// Addresses the request is forwarded for, starting with the original client.
+///
+/// Only `forwarded_header` is read, proxies usually pass the other one
+/// through unchanged so the client could make up any address with it.
+fn forwarded_for(headers: &HeaderMap, forwarded_header: ForwardedHeader) -> Vec<&str> {
+    match forwarded_header {
+        ForwardedHeader::Forwarded => headers
+            .get_all(header::FORWARDED)
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(|v| {
+                v.split(';').find_map(|pair| {
+                    let (name, value) = pair.trim().split_once('=')?;
+                    name.eq_ignore_ascii_case("for").then_some(value)
+                })
+            })
+            .collect(),
+        ForwardedHeader::XForwardedFor => headers
+            .get_all("x-forwarded-for")
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .collect(),
+    }
+}
+
+/// Gets the client IP from the peer address.
+///
+/// Requests from trusted proxies (and the Unix socket, which doesn't have any
+/// peer address) use their `forwarded_header` instead. The
+/// last address not of a trusted proxy is the client since anything before
+/// it could be made up by the client itself.
+pub fn client_ip(req: &HttpRequest, server: &ServerConfig) -> Result<IpAddr, &'static str> {
+    let trusted_proxies = &server.trusted_proxies;
+    let peer = req.peer_addr().map(|v| v.ip());
+    if let Some(peer) = peer.filter(|v| !trusted_proxies.contains(v)) {
+        return Ok(peer);
+    }
+
+    let mut client = None;
+    for value in forwarded_for(req.headers(), server.forwarded_header)
+        .into_iter()
+        .rev()
+    {
+        let ip = parse_forwarded_ip(value)
+            .ok_or("Reverse proxy forwarded an invalid client IP address")?;
+        client = Some(ip);
+        if !trusted_proxies.contains(&ip) {
+            break;
+        }
+    }
+
+    client
+        .or(peer)
+        .ok_or("Could not extract client IP address, is the reverse proxy forwarding it?")
+}
+
+type KeyedRateLimiter = RateLimiter<IpAddr, DefaultKeyedStateStore<IpAddr>, DefaultClock>;
+
+fn keyed_rate_limiter(policy: RateLimitPolicy) -> KeyedRateLimiter {
+    // both are at least 1 once the config is loaded
+    let quota = Quota::with_period(Duration::from_secs(policy.per_second))
+        .expect("rate limit period must not be zero")
+        .allow_burst(NonZeroU32::new(policy.burst_size).expect("burst size must not be zero"));
+    RateLimiter::keyed(quota)
+}
+
+/// Routes having their own rate limit policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RateLimitedRoute {
+    Read,
+    PostLetter,
+    ReportLetter,
+    Login,
+    Other,
+}
+
+impl RateLimitedRoute {
+    fn of(req: &ServiceRequest) -> Self {
+        let path = req.path();
+        match *req.method() {
+            Method::GET | Method::HEAD => Self::Read,
+            Method::POST if path == "/api/letters/post" => Self::PostLetter,
+            Method::POST if path.starts_with("/api/letters/report/") => Self::ReportLetter,
+            Method::POST
+                if matches!(
+                    path,
+                    "/api/users/login" | "/api/users/login/totp" | "/api/users/register"
+                ) =>
+            {
+                Self::Login
+            }
+            _ => Self::Other,
+        }
+    }
+}
+
+/// Rate limits API requests by the client IP, with
+/// separate limits for the routes in [`RateLimitedRoute`].
+pub struct RateLimits {
+    read: KeyedRateLimiter,
+    post_letter: KeyedRateLimiter,
+    report_letter: KeyedRateLimiter,
+    login: KeyedRateLimiter,
+    other: KeyedRateLimiter,
+    exempt_staff: bool,
+    /// Sessions already checked for being staff, until when it's trusted
+    staff_sessions: Mutex<HashMap<Uuid, (bool, Instant)>>,
+    token_key: Vec<u8>,
+    server: ServerConfig,
+}
+
+impl RateLimits {
+    pub fn new(config: &Config, auth: &AuthParams) -> Self {
+        let rate_limit = &config.rate_limit;
+        Self {
+            read: keyed_rate_limiter(rate_limit.read),
+            post_letter: keyed_rate_limiter(rate_limit.post_letter),
+            report_letter: keyed_rate_limiter(rate_limit.report_letter),
+            login: keyed_rate_limiter(rate_limit.login),
+            other: keyed_rate_limiter(rate_limit.default_policy()),
+            exempt_staff: rate_limit.exempt_staff,
+            staff_sessions: Mutex::new(HashMap::new()),
+            token_key: auth.token.clone(),
+            server: config.server.clone(),
+        }
+    }
+
+    fn limiter(&self, route: RateLimitedRoute) -> &KeyedRateLimiter {
+        match route {
+            RateLimitedRoute::Read => &self.read,
+            RateLimitedRoute::PostLetter => &self.post_letter,
+            RateLimitedRoute::ReportLetter => &self.report_letter,
+            RateLimitedRoute::Login => &self.login,
+            RateLimitedRoute::Other => &self.other,
+        }
+    }
+
+    /// Staff are users having any permission on a session that's still
+    /// active. It's cached for a bit so not every request hits the database,
+    /// meaning revoked sessions and roles keep being exempt a little longer.
+    async fn is_staff(&self, req: &ServiceRequest) -> bool {
+        let token = match bearer_token(req.headers()) {
+            Some(n) => n.to_string(),
+            None => match req.cookie(TOKEN_COOKIE) {
+                Some(n) => n.value().to_string(),
+                None => return false,
+            },
+        };
+        let claims = match UserToken::decode_token(&token, &self.token_key) {
+            Ok(n) => n.claims,
+            Err(_) => return false,
+        };
+        let user_id = Uuid::from_str(&claims.sub);
+        let (user_id, session_id) = match (user_id, Uuid::from_str(&claims.sid)) {
+            (Ok(user_id), Ok(session_id)) => (user_id, session_id),
+            _ => return false,
+        };
+
+        let now = Instant::now();
+        if let Some((is_staff, until)) = self.staff_sessions.lock().unwrap().get(&session_id) {
+            if *until > now {
+                return *is_staff;
+            }
+        }
+
+        let pool = match req.app_data::<DbPool>() {
+            Some(n) => n.clone(),
+            None => return false,
+        };
+        let result = db::run(&pool, move |conn| {
+            let now = chrono::Utc::now().naive_utc();
+            match db::sessions::find_active_user(conn, session_id, user_id, now)? {
+                Some(_) => Ok(!db::roles::permissions_of_user(conn, user_id)?.is_empty()),
+                None => Ok(false),
+            }
+        })
+        .await;
+
+        match result {
+            Ok(is_staff) => {
+                self.staff_sessions
+                    .lock()
+                    .unwrap()
+                    .insert(session_id, (is_staff, now + STAFF_CACHE_DURATION));
+                is_staff
+            }
+            Err(e) => {
+                log::warn!("[RateLimits] failed to check if the user is staff: {}", e);
+                false
+            }
+        }
+    }
+
+    pub async fn check(&self, req: &ServiceRequest) -> Result<(), actix_web::Error> {
+        if self.exempt_staff && self.is_staff(req).await {
+            return Ok(());
+        }
+
+        let ip = client_ip(req.request(), &self.server).map_err(|e| {
+            log::warn!("[RateLimits] {}", e);
+            error::ErrorInternalServerError(e)
+        })?;
+
+        let route = RateLimitedRoute::of(req);
+        match self.limiter(route).check_key(&ip) {
+            Ok(()) => Ok(()),
+            Err(not_until) => {
+                let wait = not_until.wait_time_from(DefaultClock::default().now());
+                log::debug!("[RateLimits] {} is limited on {:?}", ip, route);
+                Err(error::ErrorTooManyRequests(format!(
+                    "Too many requests, retry in {}s",
+                    wait.as_secs().max(1)
+                ))
+                .into())
+            }
+        }
+    }
+
+    /// Forgets clients which are allowed a full burst again
+    pub fn retain_recent(&self) {
+        for limiter in [
+            &self.read,
+            &self.post_letter,
+            &self.report_letter,
+            &self.login,
+            &self.other,
+        ] {
+            limiter.retain_recent();
+            limiter.shrink_to_fit();
+        }
+
+        let now = Instant::now();
+        let mut staff_sessions = self.staff_sessions.lock().unwrap();
+        staff_sessions.retain(|_, (_, until)| *until > now);
+        staff_sessions.shrink_to_fit();
+    }
+}
+
+/// Middleware applying [`RateLimits`] to every request of the wrapped service
+pub struct RateLimit(pub Arc<RateLimits>);
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service: Rc::new(service),
+            rate_limits: self.0.clone(),
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    rate_limits: Arc<RateLimits>,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let rate_limits = self.rate_limits.clone();
+        Box::pin(async move {
+            rate_limits.check(&req).await?;
+            service.call(req).await
+        })
+    }
+}
+
+/// Failed login counters of a username and the IP address trying it
+pub struct LoginThrottle {
+    user_key: String,
+    ip: Option<String>,
+    config: LoginThrottleConfig,
+}
+
+fn locked_error(until: NaiveDateTime) -> ApiError {
+    // rounded up so it's never "0 seconds"
+    let millis = (until - chrono::Utc::now().naive_utc()).num_milliseconds();
+    let secs = (millis + 999) / 1000;
+    error::ErrorTooManyRequests(format!(
+        "Too many failed logins, try again in {} seconds",
+        secs
+    ))
+}
+
+impl LoginThrottle {
+    pub fn new(config: &Config, req: &HttpRequest, username: &str) -> Self {
+        let ip = match client_ip(req, &config.server) {
+            Ok(n) => Some(n.to_string()),
+            Err(e) => {
+                log::warn!("[LoginThrottle] only counting by username: {}", e);
+                None
+            }
+        };
+
+        Self {
+            user_key: user_throttle_key(username),
+            ip,
+            config: config.login_throttle.clone(),
+        }
+    }
+
+    fn keys(&self) -> Vec<(String, u32)> {
+        let mut keys = vec![(self.user_key.clone(), self.config.max_user_failures)];
+        if let Some(ip) = self.ip.as_deref() {
+            keys.push((ip_throttle_key(ip), self.config.max_ip_failures));
+        }
+        keys
+    }
+
+    /// Rejects the login if the username or IP address is locked
+    pub async fn check(&self, pool: &DbPool) -> Result<(), ApiError> {
+        let pool = pool.clone();
+        let keys = self.keys().into_iter().map(|v| v.0).collect::<Vec<_>>();
+        let locked_until = db::run(&pool, move |conn| {
+            db::login_failures::locked_until(conn, &keys, chrono::Utc::now().naive_utc())
+        })
+        .await?;
+
+        match locked_until {
+            Some(until) => Err(locked_error(until)),
+            None => Ok(()),
+        }
+    }
+
+    /// Counts the failed login, returning `err` unless it just got locked
+    pub async fn failed(&self, pool: &DbPool, err: ApiError) -> ApiError {
+        let pool = pool.clone();
+        let keys = self.keys();
+        let ip = self.ip.clone();
+        let config = self.config.clone();
+        let result = db::run(&pool, move |conn| {
+            let now = chrono::Utc::now().naive_utc();
+
+            let mut locked_until = None;
+            for (key, max_failures) in keys {
+                let until = db::login_failures::record_failure(
+                    conn,
+                    &key,
+                    max_failures,
+                    &config,
+                    ip.as_deref(),
+                    now,
+                )?;
+                locked_until = locked_until.max(until);
+            }
+            Ok(locked_until)
+        })
+        .await;
+
+        match result {
+            Ok(Some(until)) => locked_error(until),
+            Ok(None) => err,
+            Err(e) => {
+                log::error!("[LoginThrottle] failed to record failed login: {}", e);
+                err
+            }
+        }
+    }
+
+    /// Forgets the failures of the username, the ones
+    /// of the IP address are kept since it may be shared.
+    pub async fn succeeded(&self, pool: &DbPool) -> Result<(), ApiError> {
+        let pool = pool.clone();
+        let key = self.user_key.clone();
+        db::run(&pool, move |conn| db::login_failures::clear(conn, &key)).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn server(trusted_proxies: &[&str], forwarded_header: ForwardedHeader) -> ServerConfig {
+        ServerConfig {
+            trusted_proxies: trusted_proxies.iter().map(|v| v.parse().unwrap()).collect(),
+            forwarded_header,
+            ..ServerConfig::default()
+        }
+    }
+
+    fn request(peer: Option<&str>, headers: &[(&str, &str)]) -> HttpRequest {
+        let mut req = TestRequest::default();
+        if let Some(peer) = peer {
+            req = req.peer_addr(SocketAddr::new(peer.parse().unwrap(), 12345));
+        }
+        for &header in headers {
+            req = req.append_header(header);
+        }
+        req.to_http_request()
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_forwarded_addresses() {
+        assert_eq!(parse_forwarded_ip(" 192.0.2.1"), Some(ip("192.0.2.1")));
+        assert_eq!(parse_forwarded_ip("192.0.2.1:8080"), Some(ip("192.0.2.1")));
+        assert_eq!(parse_forwarded_ip("2001:db8::1"), Some(ip("2001:db8::1")));
+        assert_eq!(
+            parse_forwarded_ip("\"[2001:db8::1]:4711\""),
+            Some(ip("2001:db8::1"))
+        );
+        assert_eq!(parse_forwarded_ip("unknown"), None);
+        assert_eq!(parse_forwarded_ip("_hidden"), None);
+    }
+
+    #[test]
+    fn reads_only_the_configured_header() {
+        let req = request(
+            None,
+            &[
+                ("x-forwarded-for", "192.0.2.1, 192.0.2.2"),
+                ("x-forwarded-for", "192.0.2.3"),
+                (
+                    "forwarded",
+                    "for=198.51.100.1;proto=https, proto=http;For=\"[2001:db8::1]\"",
+                ),
+            ],
+        );
+
+        assert_eq!(
+            forwarded_for(req.headers(), ForwardedHeader::XForwardedFor),
+            ["192.0.2.1", " 192.0.2.2", "192.0.2.3"]
+        );
+        assert_eq!(
+            forwarded_for(req.headers(), ForwardedHeader::Forwarded),
+            ["198.51.100.1", "\"[2001:db8::1]\""]
+        );
+    }
+
+    #[test]
+    fn ignores_headers_from_untrusted_peers() {
+        let server = server(&["10.0.0.1"], ForwardedHeader::XForwardedFor);
+        let req = request(Some("203.0.113.9"), &[("x-forwarded-for", "192.0.2.1")]);
+        assert_eq!(client_ip(&req, &server), Ok(ip("203.0.113.9")));
+    }
+
+    #[test]
+    fn walks_past_trusted_proxies() {
+        let server = server(&["10.0.0.1", "10.0.0.2"], ForwardedHeader::XForwardedFor);
+
+        // the client made up the first address, 10.0.0.2 is the proxy before us
+        let req = request(
+            Some("10.0.0.1"),
+            &[("x-forwarded-for", "192.0.2.66, 192.0.2.1, 10.0.0.2")],
+        );
+        assert_eq!(client_ip(&req, &server), Ok(ip("192.0.2.1")));
+
+        // only proxies, so the first one is where it came from
+        let req = request(Some("10.0.0.1"), &[("x-forwarded-for", "10.0.0.2")]);
+        assert_eq!(client_ip(&req, &server), Ok(ip("10.0.0.2")));
+    }
+
+    #[test]
+    fn uses_the_forwarded_header() {
+        let server = server(&["10.0.0.1"], ForwardedHeader::Forwarded);
+        let req = request(
+            Some("10.0.0.1"),
+            &[
+                ("x-forwarded-for", "192.0.2.66"),
+                ("forwarded", "for=\"[2001:db8::1]:4711\";proto=https"),
+            ],
+        );
+        assert_eq!(client_ip(&req, &server), Ok(ip("2001:db8::1")));
+
+        // without the configured header it's the proxy itself
+        let req = request(Some("10.0.0.1"), &[("x-forwarded-for", "192.0.2.66")]);
+        assert_eq!(client_ip(&req, &server), Ok(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn unix_socket_needs_the_header() {
+        let server = server(&[], ForwardedHeader::XForwardedFor);
+
+        let req = request(None, &[("x-forwarded-for", "192.0.2.1")]);
+        assert_eq!(client_ip(&req, &server), Ok(ip("192.0.2.1")));
+
+        let req = request(None, &[]);
+        assert!(client_ip(&req, &server).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_forwarded_addresses() {
+        let server = server(&["10.0.0.1"], ForwardedHeader::XForwardedFor);
+        let req = request(Some("10.0.0.1"), &[("x-forwarded-for", "192.0.2.1, nope")]);
+        assert!(client_ip(&req, &server).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+use std::sync::{Arc, RwLock};
+
+use actix_web::dev::Server;
+use actix_web::http::header;
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+
+use anyhow::{Context, Result};
+
+use openssl::ssl::{
+    AlpnError, SslAcceptor, SslAcceptorBuilder, SslContext, SslFiletype, SslMethod,
+};
+
+use backend_lib::config::TlsConfig;
+
+/// Loads the certificate and key into a new context with the same
+/// ALPN setup actix-web does in `bind_openssl`.
+fn load_context(config: &TlsConfig) -> Result<SslContext> {
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+
+    builder
+        .set_private_key_file(&config.key, SslFiletype::PEM)
+        .with_context(|| format!("failed to load TLS key {}", config.key.display()))?;
+    builder
+        .set_certificate_chain_file(&config.cert)
+        .with_context(|| format!("failed to load TLS certificate {}", config.cert.display()))?;
+    builder
+        .check_private_key()
+        .with_context(|| "TLS key does not match the certificate")?;
+
+    builder.set_alpn_select_callback(|_, protocols| {
+        const H2: &[u8] = b"\x02h2";
+        const H11: &[u8] = b"\x08http/1.1";
+
+        if protocols.windows(3).any(|window| window == H2) {
+            Ok(b"h2")
+        } else if protocols.windows(9).any(|window| window == H11) {
+            Ok(b"http/1.1")
+        } else {
+            Err(AlpnError::NOACK)
+        }
+    });
+
+    Ok(builder.build().into_context())
+}
+
+/// Certificates currently in use, they can be replaced without restarting
+/// the server since every connection picks them up on its handshake.
+#[derive(Clone)]
+pub struct Certificates {
+    config: TlsConfig,
+    context: Arc<RwLock<SslContext>>,
+}
+
+impl Certificates {
+    pub fn load(config: &TlsConfig) -> Result<Self> {
+        Ok(Self {
+            config: config.clone(),
+            context: Arc::new(RwLock::new(load_context(config)?)),
+        })
+    }
+
+    /// Reads the files again, the previous certificates are kept if it fails
+    pub fn reload(&self) -> Result<()> {
+        let context = load_context(&self.config)?;
+        *self.context.write().unwrap() = context;
+        Ok(())
+    }
+
+    /// Creates the acceptor for `bind_openssl`.
+    ///
+    /// The servername callback is called on every handshake (even without
+    /// SNI) so it's where the connection is switched to the latest context.
+    pub fn acceptor(&self) -> Result<SslAcceptorBuilder> {
+        let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+        builder.set_private_key_file(&self.config.key, SslFiletype::PEM)?;
+        builder.set_certificate_chain_file(&self.config.cert)?;
+
+        let context = self.context.clone();
+        builder.set_servername_callback(move |ssl, _| {
+            let context = context.read().unwrap();
+            ssl.set_ssl_context(&context).map_err(|e| {
+                log::error!("[tls] failed to switch certificates: {}", e);
+                openssl::ssl::SniError::ALERT_FATAL
+            })
+        });
+
+        Ok(builder)
+    }
+
+    /// Reloads the certificates whenever the process receives SIGHUP
+    #[cfg(unix)]
+    pub fn reload_on_sighup(self) -> Result<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = signal(SignalKind::hangup())?;
+        actix_web::rt::spawn(async move {
+            while hangup.recv().await.is_some() {
+                log::info!("[tls] received SIGHUP, reloading certificates");
+                match self.reload() {
+                    Ok(()) => log::info!("[tls] certificates reloaded"),
+                    Err(e) => log::error!("[tls] failed to reload certificates: {:#}", e),
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn reload_on_sighup(self) -> Result<()> {
+        log::warn!("[tls] certificates cannot be reloaded on this platform");
+        Ok(())
+    }
+}
+
+/// Strips the port from the `Host` header, keeping IPv6 brackets
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        // a colon inside of `[::1]` is not a port separator
+        Some((name, port))
+            if port.chars().all(|c| c.is_ascii_digit())
+                && (!name.contains(':') || name.ends_with(']')) =>
+        {
+            name
+        }
+        _ => host,
+    }
+}
+
+async fn redirect(req: HttpRequest, https_port: web::Data<u16>) -> HttpResponse {
+    let info = req.connection_info();
+    let host = strip_port(info.host());
+
+    let mut location = format!("https://{}", host);
+    if **https_port != 443 {
+        location.push_str(&format!(":{}", **https_port));
+    }
+    if let Some(path) = req.uri().path_and_query() {
+        location.push_str(path.as_str());
+    }
+
+    HttpResponse::PermanentRedirect()
+        .insert_header((header::LOCATION, location))
+        .finish()
+}
+
+/// Plain HTTP server on `redirect_port` which redirects every request to HTTPS
+pub fn redirect_server(hosts: &[String], redirect_port: u16, https_port: u16) -> Result<Server> {
+    let https_port = web::Data::new(https_port);
+    let mut server = HttpServer::new(move || {
+        App::new()
+            .app_data(https_port.clone())
+            .default_service(web::to(redirect))
+    })
+    .workers(1);
+
+    for host in hosts {
+        for listener in crate::listen::bind(host, redirect_port)? {
+            server = server
+                .listen(listener)
+                .with_context(|| format!("failed to listen on {}:{}", host, redirect_port))?;
+        }
+    }
+
+    for addr in server.addrs() {
+        log::info!("Redirecting http://{} to HTTPS", addr);
+    }
+
+    Ok(server.run())
+}
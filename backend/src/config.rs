@@ -1,62 +1,1099 @@
 use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Deserializer};
 
-use std::net::Ipv4Addr;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
-macro_rules! generate_16_bytes_fn {
-    ($name:ident, $env:literal) => {
-        pub fn $name() -> Result<Vec<u8>> {
-            let key = std::env::var($env).with_context(|| concat!($env, " is not present"))?;
-            if key.len() != 16 {
-                Err(anyhow!(concat!($env, " is not 16 characters/bytes long!")))
-            } else {
-                Ok(key.as_bytes().to_vec())
-            }
-        }
-    };
-}
-
 // not secure but close enough to be
+#[derive(Clone)]
 pub struct AuthParams {
     pub salt: [u8; 16],
     pub token: Vec<u8>,
-    pub reg: [u8; 16],
 
-    pub secret_key: [u8; 16],
+    pub secret_keys: SecretKeys,
+}
+
+/// AES key for secret letters, its id is stored along
+/// with every message encrypted by it
+#[derive(Clone)]
+pub struct SecretKey {
+    pub id: String,
+    pub key: [u8; 16],
 }
 
-generate_16_bytes_fn!(reg_key, "REGISTER_KEY");
-generate_16_bytes_fn!(salt_key, "SALT_KEY");
-generate_16_bytes_fn!(encoding_key, "ENCODING_KEY");
+impl std::fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecretKey").field("id", &self.id).finish()
+    }
+}
 
-generate_16_bytes_fn!(secret_aes_key, "SECRET_KEY");
+/// New secret letters are encrypted with the active key, retired
+/// keys are only used to decrypt letters that are not re-encrypted yet.
+#[derive(Debug, Clone)]
+pub struct SecretKeys {
+    pub active: SecretKey,
+    pub retired: Vec<SecretKey>,
+}
 
-pub fn server_port() -> Result<u16> {
-    #[rustfmt::skip]
-    fn default() -> u16 {
-        #[cfg(not(feature = "hosting"))] { 3080 }
-        #[cfg(feature = "hosting")] { 80 }
+impl SecretKeys {
+    pub fn find(&self, id: &str) -> Option<&SecretKey> {
+        self.iter().find(|v| v.id == id)
     }
 
-    if let Ok(port) = std::env::var("PORT") {
-        port.parse::<u16>()
-            .with_context(|| "failed to parse u16 with PORT environment variable")
-    } else {
-        Ok(default())
+    /// Iterates all keys, starting with the active key
+    pub fn iter(&self) -> impl Iterator<Item = &SecretKey> {
+        std::iter::once(&self.active).chain(self.retired.iter())
     }
 }
 
-pub fn server_address() -> Result<Ipv4Addr> {
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// IP addresses or hostnames to listen on, hostnames are
+    /// bound to every address they resolve to
+    #[serde(rename = "host", deserialize_with = "one_or_many")]
+    pub hosts: Vec<String>,
+    pub port: u16,
+    /// Also listens on a Unix domain socket, like for a local reverse proxy
+    pub unix_socket: Option<PathBuf>,
+    /// Directory of the compiled frontend (`npm run build`)
+    pub static_dir: PathBuf,
+    /// Reverse proxies whose `forwarded_header` is trusted for
+    /// the client IP, the Unix socket is always trusted
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Header the trusted proxies set, the other one is ignored
+    /// since it may come straight from the client
+    pub forwarded_header: ForwardedHeader,
+}
+
+impl Default for ServerConfig {
     #[rustfmt::skip]
-    fn default() -> Ipv4Addr {
-        #[cfg(not(feature = "hosting"))] { Ipv4Addr::new(127, 0, 0, 1) }
-        #[cfg(feature = "hosting")] { Ipv4Addr::new(127, 0, 0, 1) }
+    fn default() -> Self {
+        Self {
+            #[cfg(not(feature = "hosting"))] hosts: vec!["127.0.0.1".to_string()],
+            #[cfg(feature = "hosting")] hosts: vec!["0.0.0.0".to_string()],
+            #[cfg(not(feature = "hosting"))] port: 3080,
+            #[cfg(feature = "hosting")] port: 80,
+            unix_socket: None,
+            static_dir: PathBuf::from("../frontend/build"),
+            trusted_proxies: Vec::new(),
+            forwarded_header: ForwardedHeader::XForwardedFor,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ForwardedHeader {
+    /// `X-Forwarded-For`, set by nginx and most hosting providers
+    XForwardedFor,
+    /// `Forwarded` from RFC 7239
+    Forwarded,
+}
+
+impl FromStr for ForwardedHeader {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "x-forwarded-for" => Ok(Self::XForwardedFor),
+            "forwarded" => Ok(Self::Forwarded),
+            _ => Err("expected x-forwarded-for or forwarded"),
+        }
+    }
+}
+
+/// Allows `host = "::"` along with `host = ["::", "0.0.0.0"]`
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(n) => vec![n],
+        OneOrMany::Many(n) => n,
+    })
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: String,
+    pub pool_size: u32,
+    /// How long requests wait for a free connection before giving up
+    pub acquire_timeout_secs: u64,
+    pub connect_timeout_secs: u64,
+    /// Queries running longer than this are cancelled, 0 to disable
+    pub statement_timeout_secs: u64,
+    /// Only verify migrations on startup instead of applying them
+    pub skip_migrations: bool,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            pool_size: 10,
+            acquire_timeout_secs: 5,
+            connect_timeout_secs: 5,
+            statement_timeout_secs: 30,
+            skip_migrations: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SameSitePolicy {
+    Strict,
+    Lax,
+    None,
+}
+
+impl FromStr for SameSitePolicy {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "strict" => Ok(Self::Strict),
+            "lax" => Ok(Self::Lax),
+            "none" => Ok(Self::None),
+            _ => Err("expected strict, lax or none"),
+        }
+    }
+}
+
+impl From<SameSitePolicy> for actix_web::cookie::SameSite {
+    fn from(policy: SameSitePolicy) -> Self {
+        match policy {
+            SameSitePolicy::Strict => Self::Strict,
+            SameSitePolicy::Lax => Self::Lax,
+            SameSitePolicy::None => Self::None,
+        }
+    }
+}
+
+/// Attributes of the session cookies
+#[derive(Debug, Clone)]
+pub struct CookieConfig {
+    /// Defaults to true if HTTPS is served directly or with the `hosting` feature
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: SameSitePolicy,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileCookieConfig {
+    secure: Option<bool>,
+    http_only: Option<bool>,
+    same_site: Option<SameSitePolicy>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CsrfConfig {
+    /// Origins allowed to send cookie authenticated requests besides
+    /// the one the request is sent to, like `https://example.com`
+    pub trusted_origins: Vec<String>,
+}
+
+/// A request is allowed every `per_second` seconds, up to `burst_size` at once
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitPolicy {
+    pub per_second: u64,
+    pub burst_size: u32,
+}
+
+impl RateLimitPolicy {
+    const fn new(per_second: u64, burst_size: u32) -> Self {
+        Self {
+            per_second,
+            burst_size,
+        }
+    }
+}
+
+/// Limits requests to the API by the client IP
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Routes without their own policy below
+    pub per_second: u64,
+    pub burst_size: u32,
+    /// Logged in users having any permission aren't limited
+    pub exempt_staff: bool,
+    /// `GET` requests
+    pub read: RateLimitPolicy,
+    pub post_letter: RateLimitPolicy,
+    pub report_letter: RateLimitPolicy,
+    /// Logging in and registering
+    pub login: RateLimitPolicy,
+}
+
+impl RateLimitConfig {
+    pub fn default_policy(&self) -> RateLimitPolicy {
+        RateLimitPolicy::new(self.per_second, self.burst_size)
+    }
+
+    fn policies_mut(&mut self) -> [(&'static str, &mut RateLimitPolicy); 4] {
+        [
+            ("READ", &mut self.read),
+            ("POST_LETTER", &mut self.post_letter),
+            ("REPORT_LETTER", &mut self.report_letter),
+            ("LOGIN", &mut self.login),
+        ]
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            per_second: 1,
+            burst_size: 3,
+            exempt_staff: true,
+            read: RateLimitPolicy::new(1, 30),
+            post_letter: RateLimitPolicy::new(30, 2),
+            report_letter: RateLimitPolicy::new(10, 3),
+            login: RateLimitPolicy::new(2, 5),
+        }
+    }
+}
+
+/// Failed logins allowed before logging in is locked for a while, separate
+/// from `rate_limit` so guessing passwords slowly is still stopped.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoginThrottleConfig {
+    /// Failures for a single username
+    pub max_user_failures: u32,
+    /// Failures from a single IP address, across every username
+    pub max_ip_failures: u32,
+    /// Failures older than this are forgotten
+    pub failure_window_secs: u64,
+    /// The first lockout, doubled with every following one
+    pub lockout_secs: u64,
+    pub max_lockout_secs: u64,
+}
+
+impl Default for LoginThrottleConfig {
+    fn default() -> Self {
+        Self {
+            max_user_failures: 5,
+            max_ip_failures: 20,
+            failure_window_secs: 15 * 60,
+            lockout_secs: 60,
+            max_lockout_secs: 60 * 60,
+        }
+    }
+}
+
+/// Two-factor authentication with authenticator apps
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TotpConfig {
+    /// Shown along with the username in authenticator apps
+    pub issuer: String,
+}
+
+impl Default for TotpConfig {
+    fn default() -> Self {
+        Self {
+            issuer: "web-app".to_string(),
+        }
+    }
+}
+
+/// Argon2id cost of password hashes, existing hashes are
+/// updated to it as their users log in
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasswordConfig {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for PasswordConfig {
+    // recommended by OWASP
+    fn default() -> Self {
+        Self {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+/// Length limits of user submitted fields
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub max_query_len: usize,
+
+    pub max_author_len: usize,
+    pub min_message_len: usize,
+    pub max_message_len: usize,
+
+    pub min_email_len: usize,
+    pub max_email_len: usize,
+    pub max_details_len: usize,
+
+    pub max_username_len: usize,
+    pub min_password_len: usize,
+    pub max_password_len: usize,
+
+    pub max_closed_message_len: usize,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_query_len: 15,
+
+            max_author_len: 50,
+            min_message_len: 50,
+            max_message_len: 1000,
+
+            min_email_len: 3,
+            max_email_len: 50,
+            max_details_len: 1000,
+
+            max_username_len: 50,
+            min_password_len: 12,
+            max_password_len: 50,
+
+            max_closed_message_len: 500,
+        }
+    }
+}
+
+/// Serves HTTPS directly instead of relying on a reverse proxy
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// PEM certificate chain, reloaded on SIGHUP along with the key
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// Also listens on this port to redirect plain HTTP to HTTPS
+    pub redirect_port: Option<u16>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileTlsConfig {
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    redirect_port: Option<u16>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct KeysConfig {
+    /// Not used anymore since registering needs an invite,
+    /// only accepted so older configurations still load.
+    register_key: Option<String>,
+    /// Only used to verify passwords hashed before Argon2 is used
+    salt_key: Option<String>,
+    encoding_key: Option<String>,
+    secret_key: Option<String>,
+    secret_key_id: Option<String>,
+    /// id -> key
+    retired_secret_keys: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    server: ServerConfig,
+    database: DatabaseConfig,
+    tls: FileTlsConfig,
+    keys: KeysConfig,
+    cookies: FileCookieConfig,
+    csrf: CsrfConfig,
+    password: PasswordConfig,
+    rate_limit: RateLimitConfig,
+    login_throttle: LoginThrottleConfig,
+    totp: TotpConfig,
+    limits: LimitsConfig,
+}
+
+/// Configuration of the entire app, loaded once on startup
+#[derive(Clone)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub tls: Option<TlsConfig>,
+    pub database: DatabaseConfig,
+    /// Validated by [`Config::auth`] since only some commands need them
+    keys: KeysConfig,
+    pub cookies: CookieConfig,
+    pub csrf: CsrfConfig,
+    pub password: PasswordConfig,
+    pub rate_limit: RateLimitConfig,
+    pub login_throttle: LoginThrottleConfig,
+    pub totp: TotpConfig,
+    pub limits: LimitsConfig,
+}
+
+static DEFAULT_SECRET_KEY_ID: &str = "default";
+
+/// Empty variables are treated as missing, like the ones in `.env.example`
+fn env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+fn env_parse<T>(errors: &mut Vec<String>, name: &str, value: &mut T)
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Some(raw) = env(name) {
+        match raw.parse::<T>() {
+            Ok(n) => *value = n,
+            Err(e) => errors.push(format!("{} is invalid: {}", name, e)),
+        }
+    }
+}
+
+fn env_bool(errors: &mut Vec<String>, name: &str, value: &mut bool) {
+    if let Some(raw) = env(name) {
+        match raw.as_str() {
+            "1" | "true" => *value = true,
+            "0" | "false" => *value = false,
+            _ => errors.push(format!("{} must be either true or false", name)),
+        }
+    }
+}
+
+fn sized_key(errors: &mut Vec<String>, name: &str, key: Option<&str>) -> [u8; 16] {
+    let mut sized = [0u8; 16];
+    match key {
+        Some(key) if key.len() == 16 => crate::vec_to_sized!(key.as_bytes(), sized),
+        Some(_) => errors.push(format!("{} is not 16 characters/bytes long!", name)),
+        None => errors.push(format!("{} is not present", name)),
+    }
+    sized
+}
+
+fn secret_key(errors: &mut Vec<String>, id: &str, key: &str) -> SecretKey {
+    if id.is_empty()
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        errors.push(format!(
+            "secret key id {:?} must only contain letters, numbers, '-' or '_'",
+            id
+        ));
     }
 
-    if let Ok(host) = std::env::var("HOST") {
-        Ipv4Addr::from_str(&host)
-            .with_context(|| "failed to parse IpAddr with HOST environment variable")
+    SecretKey {
+        id: id.to_string(),
+        key: sized_key(errors, &format!("secret key {:?}", id), Some(key)),
+    }
+}
+
+/// IPv6 addresses may be written in brackets like in URLs
+fn normalize_host(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|v| v.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+fn check_host(errors: &mut Vec<String>, host: &str) {
+    if host.parse::<IpAddr>().is_ok() {
+        return;
+    }
+
+    let valid = !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+
+    if !valid {
+        errors.push(format!(
+            "server host {:?} is neither an IP address nor a hostname",
+            host
+        ));
+    }
+}
+
+fn check_range(errors: &mut Vec<String>, name: &str, min: usize, max: usize) {
+    if min > max {
+        errors.push(format!(
+            "limits.min_{0}_len must not be greater than limits.max_{0}_len",
+            name
+        ));
+    }
+}
+
+fn validate_keys(keys: &KeysConfig, errors: &mut Vec<String>) -> AuthParams {
+    let salt = sized_key(errors, "SALT_KEY", keys.salt_key.as_deref());
+    if keys.register_key.is_some() {
+        log::warn!(
+            "REGISTER_KEY is no longer used, create invites with `backend-bin invite create` instead"
+        );
+    }
+    let token = sized_key(errors, "ENCODING_KEY", keys.encoding_key.as_deref()).to_vec();
+
+    let active_id = keys
+        .secret_key_id
+        .as_deref()
+        .unwrap_or(DEFAULT_SECRET_KEY_ID)
+        .to_string();
+
+    let active = match keys.secret_key.as_deref() {
+        Some(key) => secret_key(errors, &active_id, key),
+        None => {
+            errors.push("SECRET_KEY is not present".to_string());
+            SecretKey {
+                id: active_id,
+                key: [0; 16],
+            }
+        }
+    };
+
+    let mut retired = Vec::new();
+    for (id, key) in keys.retired_secret_keys.iter() {
+        if *id == active.id {
+            errors.push(format!("secret key id {:?} is used more than once", id));
+        }
+        retired.push(secret_key(errors, id, key));
+    }
+
+    AuthParams {
+        salt,
+        token,
+        secret_keys: SecretKeys { active, retired },
+    }
+}
+
+fn check_errors(errors: Vec<String>) -> Result<()> {
+    if errors.is_empty() {
+        Ok(())
     } else {
-        Ok(default())
+        Err(anyhow!(
+            "invalid configuration:\n  - {}",
+            errors.join("\n  - ")
+        ))
+    }
+}
+
+impl Config {
+    /// Loads the configuration from the TOML file (if there's any) and
+    /// environment variables, where environment variables take precedence.
+    ///
+    /// Every invalid or missing option is reported at once.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let mut file = match path {
+            Some(path) => {
+                log::debug!("Loading config file from {}", path.display());
+                let content = std::fs::read_to_string(path)
+                    .with_context(|| format!("failed to read config file {}", path.display()))?;
+
+                toml::from_str::<FileConfig>(&content)
+                    .with_context(|| format!("failed to parse config file {}", path.display()))?
+            }
+            None => FileConfig::default(),
+        };
+
+        let mut errors = Vec::new();
+
+        if let Some(hosts) = env("HOST") {
+            file.server.hosts = hosts
+                .split(',')
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .collect();
+        }
+        env_parse(&mut errors, "PORT", &mut file.server.port);
+        if let Some(path) = env("UNIX_SOCKET") {
+            file.server.unix_socket = Some(PathBuf::from(path));
+        }
+        env_parse(&mut errors, "STATIC_DIR", &mut file.server.static_dir);
+        if let Some(proxies) = env("TRUSTED_PROXIES") {
+            file.server.trusted_proxies.clear();
+            for proxy in proxies.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+                match proxy.parse() {
+                    Ok(n) => file.server.trusted_proxies.push(n),
+                    Err(e) => errors.push(format!("TRUSTED_PROXIES is invalid: {}", e)),
+                }
+            }
+        }
+        env_parse(
+            &mut errors,
+            "FORWARDED_HEADER",
+            &mut file.server.forwarded_header,
+        );
+
+        if let Some(path) = env("TLS_CERT") {
+            file.tls.cert = Some(PathBuf::from(path));
+        }
+        if let Some(path) = env("TLS_KEY") {
+            file.tls.key = Some(PathBuf::from(path));
+        }
+        if let Some(port) = env("TLS_REDIRECT_PORT") {
+            match port.parse() {
+                Ok(n) => file.tls.redirect_port = Some(n),
+                Err(e) => errors.push(format!("TLS_REDIRECT_PORT is invalid: {}", e)),
+            }
+        }
+
+        env_parse(&mut errors, "DATABASE_URL", &mut file.database.url);
+        env_parse(
+            &mut errors,
+            "DATABASE_POOL_SIZE",
+            &mut file.database.pool_size,
+        );
+        env_parse(
+            &mut errors,
+            "DATABASE_ACQUIRE_TIMEOUT_SECS",
+            &mut file.database.acquire_timeout_secs,
+        );
+        env_parse(
+            &mut errors,
+            "DATABASE_CONNECT_TIMEOUT_SECS",
+            &mut file.database.connect_timeout_secs,
+        );
+        env_parse(
+            &mut errors,
+            "DATABASE_STATEMENT_TIMEOUT_SECS",
+            &mut file.database.statement_timeout_secs,
+        );
+        env_bool(
+            &mut errors,
+            "SKIP_MIGRATIONS",
+            &mut file.database.skip_migrations,
+        );
+
+        for (name, value) in [
+            ("COOKIE_SECURE", &mut file.cookies.secure),
+            ("COOKIE_HTTP_ONLY", &mut file.cookies.http_only),
+        ] {
+            if env(name).is_some() {
+                let mut flag = false;
+                env_bool(&mut errors, name, &mut flag);
+                *value = Some(flag);
+            }
+        }
+        if let Some(policy) = env("COOKIE_SAME_SITE") {
+            match policy.parse() {
+                Ok(n) => file.cookies.same_site = Some(n),
+                Err(e) => errors.push(format!("COOKIE_SAME_SITE is invalid: {}", e)),
+            }
+        }
+        if let Some(origins) = env("CSRF_TRUSTED_ORIGINS") {
+            file.csrf.trusted_origins = origins
+                .split(',')
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .collect();
+        }
+
+        env_parse(
+            &mut errors,
+            "PASSWORD_MEMORY_KIB",
+            &mut file.password.memory_kib,
+        );
+        env_parse(
+            &mut errors,
+            "PASSWORD_ITERATIONS",
+            &mut file.password.iterations,
+        );
+        env_parse(
+            &mut errors,
+            "PASSWORD_PARALLELISM",
+            &mut file.password.parallelism,
+        );
+
+        env_parse(
+            &mut errors,
+            "RATE_LIMIT_PER_SECOND",
+            &mut file.rate_limit.per_second,
+        );
+        env_parse(
+            &mut errors,
+            "RATE_LIMIT_BURST_SIZE",
+            &mut file.rate_limit.burst_size,
+        );
+        env_bool(
+            &mut errors,
+            "RATE_LIMIT_EXEMPT_STAFF",
+            &mut file.rate_limit.exempt_staff,
+        );
+        for (name, policy) in file.rate_limit.policies_mut() {
+            env_parse(
+                &mut errors,
+                &format!("RATE_LIMIT_{}_PER_SECOND", name),
+                &mut policy.per_second,
+            );
+            env_parse(
+                &mut errors,
+                &format!("RATE_LIMIT_{}_BURST_SIZE", name),
+                &mut policy.burst_size,
+            );
+        }
+
+        let throttle = &mut file.login_throttle;
+        env_parse(
+            &mut errors,
+            "LOGIN_MAX_USER_FAILURES",
+            &mut throttle.max_user_failures,
+        );
+        env_parse(
+            &mut errors,
+            "LOGIN_MAX_IP_FAILURES",
+            &mut throttle.max_ip_failures,
+        );
+        env_parse(
+            &mut errors,
+            "LOGIN_FAILURE_WINDOW_SECS",
+            &mut throttle.failure_window_secs,
+        );
+        env_parse(
+            &mut errors,
+            "LOGIN_LOCKOUT_SECS",
+            &mut throttle.lockout_secs,
+        );
+        env_parse(
+            &mut errors,
+            "LOGIN_MAX_LOCKOUT_SECS",
+            &mut throttle.max_lockout_secs,
+        );
+
+        env_parse(&mut errors, "TOTP_ISSUER", &mut file.totp.issuer);
+
+        let keys = &mut file.keys;
+        for (name, value) in [
+            ("REGISTER_KEY", &mut keys.register_key),
+            ("SALT_KEY", &mut keys.salt_key),
+            ("ENCODING_KEY", &mut keys.encoding_key),
+            ("SECRET_KEY", &mut keys.secret_key),
+            ("SECRET_KEY_ID", &mut keys.secret_key_id),
+        ] {
+            if let Some(n) = env(name) {
+                *value = Some(n);
+            }
+        }
+
+        if let Some(retired) = env("RETIRED_SECRET_KEYS") {
+            keys.retired_secret_keys.clear();
+            for entry in retired.split(',').filter(|v| !v.trim().is_empty()) {
+                match entry.trim().split_once(':') {
+                    Some((id, key)) => {
+                        keys.retired_secret_keys
+                            .insert(id.to_string(), key.to_string());
+                    }
+                    None => errors.push(
+                        "RETIRED_SECRET_KEYS entries must be formatted as id:key".to_string(),
+                    ),
+                }
+            }
+        }
+
+        let config = Self::validate(file, &mut errors);
+        check_errors(errors)?;
+        Ok(config)
+    }
+
+    /// Keys for sessions, legacy passwords and secret letters, which
+    /// are only required by the commands using them (like `serve`).
+    pub fn auth(&self) -> Result<AuthParams> {
+        let mut errors = Vec::new();
+        let auth = validate_keys(&self.keys, &mut errors);
+        check_errors(errors)?;
+        Ok(auth)
+    }
+
+    fn validate(mut file: FileConfig, errors: &mut Vec<String>) -> Self {
+        for host in file.server.hosts.iter_mut() {
+            *host = normalize_host(host.trim()).to_string();
+            check_host(errors, host);
+        }
+        if file.server.hosts.is_empty() && file.server.unix_socket.is_none() {
+            errors.push("server.host or server.unix_socket must be set".to_string());
+        }
+        if cfg!(not(unix)) && file.server.unix_socket.is_some() {
+            errors.push("server.unix_socket is only supported on Unix".to_string());
+        }
+
+        let tls = match (file.tls.cert, file.tls.key) {
+            (Some(cert), Some(key)) => {
+                for path in [&cert, &key] {
+                    if !path.is_file() {
+                        errors.push(format!("TLS file {} does not exist", path.display()));
+                    }
+                }
+                if file.tls.redirect_port == Some(file.server.port) {
+                    errors.push("tls.redirect_port must not be the same as server.port".into());
+                }
+                Some(TlsConfig {
+                    cert,
+                    key,
+                    redirect_port: file.tls.redirect_port,
+                })
+            }
+            (None, None) => {
+                if file.tls.redirect_port.is_some() {
+                    errors.push("tls.redirect_port requires tls.cert and tls.key".to_string());
+                }
+                None
+            }
+            _ => {
+                errors.push("tls.cert and tls.key must be set together".to_string());
+                None
+            }
+        };
+
+        let cookies = CookieConfig {
+            secure: file
+                .cookies
+                .secure
+                .unwrap_or(tls.is_some() || cfg!(feature = "hosting")),
+            http_only: file.cookies.http_only.unwrap_or(true),
+            same_site: file.cookies.same_site.unwrap_or(SameSitePolicy::Strict),
+        };
+        if cookies.same_site == SameSitePolicy::None && !cookies.secure {
+            errors.push("cookies.same_site = \"none\" requires cookies.secure".to_string());
+        }
+
+        for origin in file.csrf.trusted_origins.iter_mut() {
+            *origin = origin.trim_end_matches('/').to_ascii_lowercase();
+            if !origin.starts_with("http://") && !origin.starts_with("https://") {
+                errors.push(format!(
+                    "trusted origin {:?} must start with http:// or https://",
+                    origin
+                ));
+            }
+        }
+
+        if file.database.url.is_empty() {
+            errors.push("DATABASE_URL (database.url) is not present".to_string());
+        }
+        if file.database.pool_size == 0 {
+            errors.push("database.pool_size must be at least 1".to_string());
+        }
+        if file.database.acquire_timeout_secs == 0 || file.database.connect_timeout_secs == 0 {
+            errors.push(
+                "database.acquire_timeout_secs and database.connect_timeout_secs must be at least 1"
+                    .to_string(),
+            );
+        }
+        if file.totp.issuer.trim().is_empty() {
+            errors.push("totp.issuer must not be empty".into());
+        }
+
+        if file.rate_limit.per_second == 0 || file.rate_limit.burst_size == 0 {
+            errors
+                .push("rate_limit.per_second and rate_limit.burst_size must be at least 1".into());
+        }
+        for (name, policy) in file.rate_limit.policies_mut() {
+            if policy.per_second == 0 || policy.burst_size == 0 {
+                errors.push(format!(
+                    "rate_limit.{name}.per_second and rate_limit.{name}.burst_size must be at least 1",
+                    name = name.to_ascii_lowercase()
+                ));
+            }
+        }
+
+        let throttle = &file.login_throttle;
+        if throttle.max_user_failures == 0 || throttle.max_ip_failures == 0 {
+            errors.push(
+                "login_throttle.max_user_failures and login_throttle.max_ip_failures must be at least 1"
+                    .into(),
+            );
+        }
+        if throttle.failure_window_secs == 0 || throttle.lockout_secs == 0 {
+            errors.push(
+                "login_throttle.failure_window_secs and login_throttle.lockout_secs must be at least 1"
+                    .into(),
+            );
+        }
+        if throttle.max_lockout_secs < throttle.lockout_secs {
+            errors.push(
+                "login_throttle.max_lockout_secs must not be less than login_throttle.lockout_secs"
+                    .into(),
+            );
+        }
+
+        let password = &file.password;
+        if let Err(e) = argon2::Params::new(
+            password.memory_kib,
+            password.iterations,
+            password.parallelism,
+            None,
+        ) {
+            errors.push(format!("password hashing cost is invalid: {}", e));
+        }
+
+        let limits = &file.limits;
+        check_range(
+            errors,
+            "message",
+            limits.min_message_len,
+            limits.max_message_len,
+        );
+        check_range(errors, "email", limits.min_email_len, limits.max_email_len);
+        check_range(
+            errors,
+            "password",
+            limits.min_password_len,
+            limits.max_password_len,
+        );
+
+        Self {
+            server: file.server,
+            tls,
+            database: file.database,
+            keys: file.keys,
+            cookies,
+            csrf: file.csrf,
+            password: file.password,
+            rate_limit: file.rate_limit,
+            login_throttle: file.login_throttle,
+            totp: file.totp,
+            limits: file.limits,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // the environment is shared by every test running at the same time
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    // could be set by whoever runs the tests
+    static CLEARED_VARS: [&str; 10] = [
+        "HOST",
+        "PORT",
+        "DATABASE_URL",
+        "DATABASE_POOL_SIZE",
+        "COOKIE_SECURE",
+        "SALT_KEY",
+        "ENCODING_KEY",
+        "SECRET_KEY",
+        "SECRET_KEY_ID",
+        "RETIRED_SECRET_KEYS",
+    ];
+
+    /// Loads the config with only `vars` set out of `CLEARED_VARS`
+    fn load(file: &str, vars: &[(&str, &str)]) -> Result<Config> {
+        let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let path = std::env::temp_dir().join(format!("config-test-{}.toml", std::process::id()));
+        std::fs::write(&path, file).unwrap();
+
+        let saved = CLEARED_VARS
+            .iter()
+            .map(|&name| (name, std::env::var(name).ok()))
+            .collect::<Vec<_>>();
+        for name in CLEARED_VARS {
+            std::env::remove_var(name);
+        }
+        for (name, value) in vars {
+            std::env::set_var(name, value);
+        }
+
+        let config = Config::load(Some(&path));
+
+        for (name, value) in saved {
+            match value {
+                Some(n) => std::env::set_var(name, n),
+                None => std::env::remove_var(name),
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+        config
+    }
+
+    static FILE: &str = r#"
+        [server]
+        host = "127.0.0.1"
+        port = 3080
+
+        [database]
+        url = "postgres://localhost/file"
+
+        [keys]
+        salt_key = "0123456789abcdef"
+        encoding_key = "0123456789abcdef"
+        secret_key = "0123456789abcdef"
+    "#;
+
+    #[test]
+    fn env_takes_precedence() {
+        let config = load(FILE, &[("PORT", "4000"), ("HOST", "::1, localhost")]).unwrap();
+        assert_eq!(config.server.port, 4000);
+        assert_eq!(config.server.hosts, ["::1", "localhost"]);
+        // not overridden
+        assert_eq!(config.database.url, "postgres://localhost/file");
+
+        let auth = config.auth().unwrap();
+        assert_eq!(auth.secret_keys.active.id, DEFAULT_SECRET_KEY_ID);
+        assert_eq!(&auth.secret_keys.active.key, b"0123456789abcdef");
+    }
+
+    #[test]
+    fn empty_env_is_missing() {
+        let config = load(FILE, &[("PORT", ""), ("DATABASE_URL", "")]).unwrap();
+        assert_eq!(config.server.port, 3080);
+        assert_eq!(config.database.url, "postgres://localhost/file");
+    }
+
+    #[test]
+    fn reports_every_error() {
+        let err = load(
+            FILE,
+            &[
+                ("PORT", "port"),
+                ("DATABASE_POOL_SIZE", "0"),
+                ("COOKIE_SECURE", "maybe"),
+            ],
+        )
+        .err()
+        .unwrap()
+        .to_string();
+
+        assert!(err.starts_with("invalid configuration:"), "{}", err);
+        for expected in [
+            "PORT is invalid",
+            "database.pool_size must be at least 1",
+            "COOKIE_SECURE must be either true or false",
+        ] {
+            assert!(
+                err.contains(expected),
+                "{:?} missing from {}",
+                expected,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn keys_are_only_required_by_auth() {
+        let file = FILE.replace("secret_key = \"0123456789abcdef\"", "");
+        let config = load(&file, &[("ENCODING_KEY", "short")]).unwrap();
+
+        let err = config.auth().err().unwrap().to_string();
+        assert!(err.contains("SECRET_KEY is not present"), "{}", err);
+        assert!(err.contains("ENCODING_KEY is not 16"), "{}", err);
+        assert!(!err.contains("SALT_KEY"), "{}", err);
     }
 }
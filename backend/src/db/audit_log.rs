@@ -0,0 +1,65 @@
+use crate::models::{self, AuditAction, NewAuditEntry};
+
+use anyhow::Result;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+/// Call it in the same transaction as the action itself,
+/// so there's never an action without its entry.
+pub fn insert(conn: &mut PgConnection, entry: NewAuditEntry) -> Result<()> {
+    log::info!(
+        "[insert] actor = {:?}; action = {}",
+        entry.actor_id,
+        entry.action
+    );
+    use crate::schema::audit_log::dsl::*;
+
+    diesel::insert_into(audit_log)
+        .values(&entry)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Narrows down the audit log, every filter is optional
+#[derive(Debug, Default, Clone)]
+pub struct AuditLogFilter {
+    pub actor_id: Option<Uuid>,
+    pub action: Option<AuditAction>,
+    /// Matches the letter, report or user the action is about
+    pub target_id: Option<Uuid>,
+}
+
+/// Gets entries starting from the newest one, entries of the same
+/// transaction share their timestamp so the id keeps pages stable.
+pub fn get_all(
+    conn: &mut PgConnection,
+    filter: &AuditLogFilter,
+    limit: usize,
+    offset: usize,
+) -> Result<Vec<models::AuditEntry>> {
+    log::info!("[get_all] filter = {:?}; offset = {}", filter, offset);
+    use crate::schema::audit_log::dsl::*;
+
+    let mut query = audit_log
+        .order((created_at.desc(), id.desc()))
+        .offset(offset as i64)
+        .limit(limit as i64)
+        .into_boxed();
+
+    if let Some(actor) = filter.actor_id {
+        query = query.filter(actor_id.eq(actor));
+    }
+    if let Some(entry_action) = filter.action {
+        query = query.filter(action.eq(entry_action.as_str()));
+    }
+    if let Some(target) = filter.target_id {
+        query = query.filter(
+            letter_id
+                .eq(target)
+                .or(report_id.eq(target))
+                .or(user_id.eq(target)),
+        );
+    }
+
+    Ok(query.load::<models::AuditEntry>(conn)?)
+}
@@ -0,0 +1,166 @@
+use crate::models::{self, NewInvite, NewInviteRole, NewUser};
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Creates an invite giving the roles to whoever uses it, the roles must exist
+pub fn insert(
+    conn: &mut PgConnection,
+    new_invite: NewInvite,
+    role_names: &[String],
+) -> Result<models::Invite> {
+    use crate::schema::invite_roles::dsl::invite_roles;
+    use crate::schema::invites::dsl::*;
+    log::info!("[insert] created_by = {:?}", new_invite.created_by);
+
+    conn.transaction(|conn| {
+        let invite = diesel::insert_into(invites)
+            .values(new_invite)
+            .get_result::<models::Invite>(conn)?;
+
+        let values = role_names
+            .iter()
+            .map(|v| NewInviteRole {
+                invite_id: invite.id,
+                role: v,
+            })
+            .collect::<Vec<_>>();
+
+        diesel::insert_into(invite_roles)
+            .values(values)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+
+        Ok(invite)
+    })
+}
+
+pub fn find_usable(
+    conn: &mut PgConnection,
+    invite_code_hash: &str,
+    now: NaiveDateTime,
+) -> Result<Option<models::Invite>> {
+    use crate::schema::invites::dsl::*;
+
+    let invite = invites
+        .filter(code_hash.eq(invite_code_hash))
+        .filter(used_at.is_null())
+        .filter(expires_at.gt(now))
+        .first::<models::Invite>(conn)
+        .optional()?;
+
+    Ok(invite)
+}
+
+/// Gets invites which are not used yet, including expired ones
+pub fn get_unused(conn: &mut PgConnection) -> Result<Vec<models::InviteInfo>> {
+    use crate::schema::invite_roles::dsl as dsl_role;
+    use crate::schema::invites::dsl::*;
+
+    let collection = invites
+        .filter(used_at.is_null())
+        .order(created_at.desc())
+        .load::<models::Invite>(conn)?;
+
+    let ids = collection.iter().map(|v| v.id).collect::<Vec<_>>();
+    let mut roles = HashMap::<Uuid, Vec<String>>::new();
+    for (invite_id, role) in dsl_role::invite_roles
+        .filter(dsl_role::invite_id.eq_any(ids))
+        .order(dsl_role::role.asc())
+        .load::<(Uuid, String)>(conn)?
+    {
+        roles.entry(invite_id).or_default().push(role);
+    }
+
+    Ok(collection
+        .into_iter()
+        .map(|invite| models::InviteInfo {
+            roles: roles.remove(&invite.id).unwrap_or_default(),
+            invite,
+        })
+        .collect())
+}
+
+/// Deletes an unused invite, returning it along with its roles if it existed
+pub fn delete_unused(
+    conn: &mut PgConnection,
+    invite_id: Uuid,
+) -> Result<Option<models::InviteInfo>> {
+    use crate::schema::invite_roles::dsl as dsl_role;
+    use crate::schema::invites::dsl::*;
+    log::info!("[delete_unused] id = {}", invite_id);
+
+    conn.transaction(|conn| {
+        // the roles are deleted along with the invite
+        let roles = dsl_role::invite_roles
+            .filter(dsl_role::invite_id.eq(invite_id))
+            .order(dsl_role::role.asc())
+            .select(dsl_role::role)
+            .load::<String>(conn)?;
+
+        let invite = diesel::delete(invites.filter(id.eq(invite_id)).filter(used_at.is_null()))
+            .get_result::<models::Invite>(conn)
+            .optional()?;
+
+        Ok(invite.map(|invite| models::InviteInfo { invite, roles }))
+    })
+}
+
+pub enum Redeemed {
+    Registered(models::User),
+    InvalidInvite,
+    UsernameTaken,
+}
+
+/// Registers the user and uses up the invite in one go, so
+/// the same invite can't create two users at the same time.
+pub fn redeem(
+    conn: &mut PgConnection,
+    invite_code_hash: &str,
+    new_user: NewUser,
+    now: NaiveDateTime,
+) -> Result<Redeemed> {
+    use crate::schema::invite_roles::dsl as dsl_role;
+    use crate::schema::invites::dsl::*;
+
+    let redeemed = conn.transaction(|conn| {
+        // the row stays locked until the transaction is over
+        let invite = diesel::update(
+            invites
+                .filter(code_hash.eq(invite_code_hash))
+                .filter(used_at.is_null())
+                .filter(expires_at.gt(now)),
+        )
+        .set(used_at.eq(now))
+        .get_result::<models::Invite>(conn)
+        .optional()?;
+
+        let invite = match invite {
+            Some(n) => n,
+            None => return Ok(Redeemed::InvalidInvite),
+        };
+
+        let user = crate::db::users::insert(conn, new_user)?;
+        diesel::update(invites.filter(id.eq(invite.id)))
+            .set(used_by.eq(user.id))
+            .execute(conn)?;
+
+        let roles = dsl_role::invite_roles
+            .filter(dsl_role::invite_id.eq(invite.id))
+            .select(dsl_role::role)
+            .load::<String>(conn)?;
+        crate::db::roles::set_user_roles(conn, user.id, &roles)?;
+
+        log::info!("[redeem] invite {} is used by {}", invite.id, user.id);
+        Ok(Redeemed::Registered(user))
+    });
+
+    // the invite isn't used up since everything is rolled back
+    match redeemed {
+        Err(e) if crate::db::users::is_name_taken(&e) => Ok(Redeemed::UsernameTaken),
+        redeemed => redeemed,
+    }
+}
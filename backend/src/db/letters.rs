@@ -1,16 +1,39 @@
-use crate::models;
+use crate::models::{self, LetterStatus};
 
 use anyhow::Result;
+use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use uuid::Uuid;
 
-pub fn delete(conn: &mut PgConnection, letter_id: Uuid) -> Result<()> {
-    log::info!("[delete] id = {}", letter_id);
-
+/// Changes the moderation status of a letter instead of deleting it,
+/// returning `None` if it doesn't exist.
+pub fn set_status(
+    conn: &mut PgConnection,
+    letter_id: Uuid,
+    new_status: LetterStatus,
+    reason: Option<&str>,
+    moderator: Uuid,
+    now: NaiveDateTime,
+) -> Result<Option<models::Letter>> {
+    log::info!(
+        "[set_status] id = {}; status = {}; moderator = {}",
+        letter_id,
+        new_status,
+        moderator
+    );
     use crate::schema::letters::dsl::*;
-    diesel::delete(letters.filter(id.eq(letter_id))).execute(conn)?;
 
-    Ok(())
+    let letter = diesel::update(letters.filter(id.eq(letter_id)))
+        .set((
+            status.eq(new_status.as_str()),
+            moderation_reason.eq(reason),
+            moderated_by.eq(moderator),
+            moderated_at.eq(now),
+        ))
+        .get_result::<models::Letter>(conn)
+        .optional()?;
+
+    Ok(letter)
 }
 
 pub fn insert(
@@ -18,6 +41,7 @@ pub fn insert(
     entry_author: impl AsRef<str>,
     entry_message: impl AsRef<str>,
     entry_secret: bool,
+    entry_status: LetterStatus,
 ) -> Result<models::Letter> {
     let entry_author = entry_author.as_ref();
     let entry_message = entry_message.as_ref();
@@ -25,13 +49,15 @@ pub fn insert(
     log::info!("[insert] posting letter");
     log::info!("[insert] author = {:?}", entry_author.len());
     log::info!("[insert] secret = {:?}", entry_secret);
+    log::info!("[insert] status = {}", entry_status);
 
     use crate::schema::letters::dsl::*;
 
     let new_letter = models::NewLetter {
-        author: &entry_author,
-        message: &entry_message,
+        author: entry_author,
+        message: entry_message,
         secret: entry_secret,
+        status: entry_status.as_str(),
     };
 
     Ok(diesel::insert_into(letters)
@@ -39,16 +65,27 @@ pub fn insert(
         .get_result(conn)?)
 }
 
+/// Gets letters of any status, or only the ones with `with_status`.
+/// Removed letters are left out unless they're asked for.
 pub fn get_all(
     conn: &mut PgConnection,
     limit: usize,
     offset: usize,
+    with_status: Option<LetterStatus>,
 ) -> Result<Vec<models::Letter>> {
-    log::info!("getting all entries");
+    log::info!("getting all entries (status = {:?})", with_status);
     use crate::schema::letters::dsl::*;
 
-    let collection = letters
-        .offset(offset.max(0) as i64)
+    let mut query = letters.into_boxed();
+    query = match with_status {
+        Some(n) => query.filter(status.eq(n.as_str())),
+        None => query.filter(status.ne(LetterStatus::Removed.as_str())),
+    };
+
+    // oldest first, so the queue is reviewed in the order letters came in
+    let collection = query
+        .order((created_at.asc(), id.asc()))
+        .offset(offset as i64)
         .limit(limit as i64)
         .load::<models::Letter>(conn)?;
 
@@ -59,23 +96,82 @@ pub fn get_all_public(
     conn: &mut PgConnection,
     limit: usize,
     offset: usize,
-) -> Result<Vec<models::Letter>> {
+) -> Result<Vec<models::PublicLetter>> {
     log::info!("getting all public entries");
     use crate::schema::letters::dsl::*;
 
     let collection = letters
         .filter(secret.eq(false))
-        .offset(offset.max(0) as i64)
+        .filter(status.eq(LetterStatus::Visible.as_str()))
+        .select((id, created_at, author, message, secret))
+        // a stable order, otherwise pages may skip or repeat letters
+        .order((created_at.asc(), id.asc()))
+        .offset(offset as i64)
         .limit(limit as i64)
-        .load::<models::Letter>(conn)?;
+        .load::<models::PublicLetter>(conn)?;
 
     Ok(collection)
 }
 
+/// Only finds visible letters, see [`find_any_by_id`] for moderation
 pub fn find_by_id(conn: &mut PgConnection, uid: &Uuid) -> Result<Option<models::Letter>> {
     log::info!("finding entry by id = {}", uid);
     use crate::schema::letters::dsl::*;
 
+    let letter = letters
+        .filter(id.eq(uid))
+        .filter(status.eq(LetterStatus::Visible.as_str()))
+        .first::<models::Letter>(conn)
+        .optional()?;
+
+    Ok(letter)
+}
+
+/// Approves or rejects a letter waiting for review, returning
+/// `None` if it doesn't exist or isn't in the queue.
+pub fn review(
+    conn: &mut PgConnection,
+    letter_id: Uuid,
+    approved: bool,
+    reason: Option<&str>,
+    moderator: Uuid,
+    now: NaiveDateTime,
+) -> Result<Option<models::Letter>> {
+    log::info!(
+        "[review] id = {}; approved = {}; moderator = {}",
+        letter_id,
+        approved,
+        moderator
+    );
+    use crate::schema::letters::dsl::*;
+
+    let new_status = match approved {
+        true => LetterStatus::Visible,
+        false => LetterStatus::Removed,
+    };
+
+    let letter = diesel::update(
+        letters
+            .filter(id.eq(letter_id))
+            .filter(status.eq(LetterStatus::PendingReview.as_str())),
+    )
+    .set((
+        status.eq(new_status.as_str()),
+        moderation_reason.eq(reason),
+        moderated_by.eq(moderator),
+        moderated_at.eq(now),
+    ))
+    .get_result::<models::Letter>(conn)
+    .optional()?;
+
+    Ok(letter)
+}
+
+/// Finds the letter whatever its moderation status is
+pub fn find_any_by_id(conn: &mut PgConnection, uid: &Uuid) -> Result<Option<models::Letter>> {
+    log::info!("finding entry of any status by id = {}", uid);
+    use crate::schema::letters::dsl::*;
+
     let letter = letters
         .filter(id.eq(uid))
         .first::<models::Letter>(conn)
@@ -95,3 +191,46 @@ pub fn find_by_author(conn: &mut PgConnection, creator: &str) -> Result<Option<m
 
     Ok(letter)
 }
+
+/// Gets letters ordered by id, starting after `after` if
+/// present so every letter can be visited in batches.
+pub fn get_batch(
+    conn: &mut PgConnection,
+    after: Option<Uuid>,
+    limit: usize,
+    secret_only: bool,
+) -> Result<Vec<models::Letter>> {
+    log::info!(
+        "[get_batch] after = {:?}; limit = {}; secret_only = {}",
+        after,
+        limit,
+        secret_only
+    );
+    use crate::schema::letters::dsl::*;
+
+    let mut query = letters.order(id.asc()).limit(limit as i64).into_boxed();
+    if secret_only {
+        query = query.filter(secret.eq(true));
+    }
+
+    if let Some(after) = after {
+        query = query.filter(id.gt(after));
+    }
+
+    Ok(query.load::<models::Letter>(conn)?)
+}
+
+pub fn update_message(
+    conn: &mut PgConnection,
+    letter_id: Uuid,
+    new_message: impl AsRef<str>,
+) -> Result<()> {
+    log::info!("[update_message] id = {}", letter_id);
+    use crate::schema::letters::dsl::*;
+
+    diesel::update(letters.filter(id.eq(letter_id)))
+        .set(message.eq(new_message.as_ref()))
+        .execute(conn)?;
+
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use crate::config::LoginThrottleConfig;
+use crate::models::{self, NewLoginLockout};
+
+use anyhow::Result;
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+
+/// When the latest lockout of the given keys ends, if any of them is locked at `now`
+pub fn locked_until(
+    conn: &mut PgConnection,
+    keys: &[String],
+    now: NaiveDateTime,
+) -> Result<Option<NaiveDateTime>> {
+    use crate::schema::login_failures::dsl::*;
+
+    Ok(login_failures
+        .filter(key.eq_any(keys))
+        .filter(locked_until.gt(now))
+        .select(diesel::dsl::max(locked_until))
+        .first::<Option<NaiveDateTime>>(conn)?)
+}
+
+/// Counts a failed login, locking the key once it fails `max_failures` times
+/// within the window. Returns when the new lockout ends if it's locked.
+pub fn record_failure(
+    conn: &mut PgConnection,
+    throttle_key: &str,
+    max_failures: u32,
+    config: &LoginThrottleConfig,
+    client_ip: Option<&str>,
+    now: NaiveDateTime,
+) -> Result<Option<NaiveDateTime>> {
+    use crate::schema::login_failures::dsl::*;
+
+    conn.transaction(|conn| {
+        prune(conn, config, now)?;
+        diesel::insert_into(login_failures)
+            .values((
+                key.eq(throttle_key),
+                failures.eq(0),
+                first_failed_at.eq(now),
+                last_failed_at.eq(now),
+            ))
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+
+        let mut entry = login_failures
+            .filter(key.eq(throttle_key))
+            .for_update()
+            .first::<models::LoginFailure>(conn)?;
+
+        entry.count_failure(config, now);
+
+        let mut lockout = None;
+        if entry.failures as u32 >= max_failures {
+            let failed = entry.failures;
+            let until = entry.lock(config, now);
+
+            diesel::insert_into(crate::schema::login_lockouts::table)
+                .values(NewLoginLockout {
+                    key: throttle_key,
+                    ip: client_ip,
+                    failures: failed,
+                    locked_until: until,
+                })
+                .execute(conn)?;
+
+            log::warn!(
+                "[record_failure] locked {} until {} after {} failed logins",
+                throttle_key,
+                until,
+                failed
+            );
+            lockout = Some(until);
+        }
+
+        diesel::update(login_failures.filter(key.eq(throttle_key)))
+            .set((
+                failures.eq(entry.failures),
+                first_failed_at.eq(entry.first_failed_at),
+                last_failed_at.eq(entry.last_failed_at),
+                locked_until.eq(entry.locked_until),
+                lockouts.eq(entry.lockouts),
+            ))
+            .execute(conn)?;
+
+        Ok(lockout)
+    })
+}
+
+/// Forgets the failures of the key after logging in successfully
+pub fn clear(conn: &mut PgConnection, throttle_key: &str) -> Result<()> {
+    use crate::schema::login_failures::dsl::*;
+
+    diesel::delete(login_failures.filter(key.eq(throttle_key))).execute(conn)?;
+    Ok(())
+}
+
+/// Removes entries which would be reset on their next failure anyway
+fn prune(
+    conn: &mut PgConnection,
+    config: &LoginThrottleConfig,
+    now: NaiveDateTime,
+) -> Result<usize> {
+    use crate::schema::login_failures::dsl::*;
+
+    let before =
+        now - Duration::seconds(config.max_lockout_secs.max(config.failure_window_secs) as i64);
+    Ok(diesel::delete(login_failures.filter(last_failed_at.lt(before))).execute(conn)?)
+}
@@ -1,21 +1,232 @@
 use actix_web::web;
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Result};
 
-use diesel::r2d2::{self, ConnectionManager};
-use diesel::PgConnection;
+use deadpool_diesel::postgres::{Hook, HookError, Manager, Pool, PoolError, Runtime};
+use deadpool_diesel::InteractError;
 
+use diesel::migration::MigrationSource;
+use diesel::pg::Pg;
+use diesel::result::DatabaseErrorKind;
+use diesel::{Connection, PgConnection, RunQueryDsl};
+
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+
+use std::fmt::Display;
+use std::time::Duration;
+
+use crate::config::DatabaseConfig;
+
+pub mod audit_log;
+pub mod invites;
 pub mod letters;
+pub mod login_failures;
 pub mod reports;
+pub mod roles;
+pub mod sessions;
 pub mod state;
+pub mod totp;
 pub mod users;
 
-pub type DbPool = web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>;
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
+
+pub type DbPool = web::Data<Pool>;
+
+/// Why a database operation failed
+#[derive(Debug)]
+pub enum DbError {
+    /// No connection is available in time, the database is down
+    /// or the query took longer than the statement timeout.
+    Unavailable(anyhow::Error),
+    Query(anyhow::Error),
+}
+
+impl DbError {
+    fn from_query(err: anyhow::Error) -> Self {
+        let unavailable = err.chain().any(|cause| {
+            if cause.is::<diesel::ConnectionError>() {
+                return true;
+            }
+            match cause.downcast_ref::<diesel::result::Error>() {
+                Some(diesel::result::Error::DatabaseError(kind, info)) => {
+                    matches!(
+                        kind,
+                        DatabaseErrorKind::ClosedConnection
+                            | DatabaseErrorKind::UnableToSendCommand
+                    ) || info.message().contains("statement timeout")
+                }
+                _ => false,
+            }
+        });
+
+        if unavailable {
+            Self::Unavailable(err)
+        } else {
+            Self::Query(err)
+        }
+    }
+}
+
+impl Display for DbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unavailable(e) => write!(f, "database is unavailable: {:#}", e),
+            Self::Query(e) => write!(f, "{:#}", e),
+        }
+    }
+}
+
+impl From<PoolError> for DbError {
+    fn from(err: PoolError) -> Self {
+        Self::Unavailable(anyhow!("failed to get a connection: {}", err))
+    }
+}
+
+impl From<InteractError> for DbError {
+    fn from(err: InteractError) -> Self {
+        Self::Query(anyhow!("database task failed: {}", err))
+    }
+}
+
+/// Runs `f` with a connection from the pool on a blocking thread
+/// since diesel would otherwise block the async workers.
+///
+/// ```ignore
+/// let user = db::run(&pool, move |conn| db::users::find_by_id(conn, &user_id)).await?;
+/// ```
+pub async fn run<F, R>(pool: &Pool, f: F) -> Result<R, DbError>
+where
+    F: FnOnce(&mut PgConnection) -> Result<R> + Send + 'static,
+    R: Send + 'static,
+{
+    let conn = pool.get().await?;
+    conn.interact(f).await?.map_err(DbError::from_query)
+}
+
+/// Connects to the database without touching its schema
+pub async fn connect_db_pool(config: &DatabaseConfig) -> Result<DbPool> {
+    log::info!(
+        "Establishing Postgres connection (pool size = {})",
+        config.pool_size
+    );
+
+    let statement_timeout = config.statement_timeout_secs;
+    let manager = Manager::new(&config.url, Runtime::Tokio1);
+    let pool = Pool::builder(manager)
+        .max_size(config.pool_size as usize)
+        .runtime(Runtime::Tokio1)
+        .wait_timeout(Some(Duration::from_secs(config.acquire_timeout_secs)))
+        .create_timeout(Some(Duration::from_secs(config.connect_timeout_secs)))
+        .post_create(Hook::async_fn(move |conn, _| {
+            Box::pin(async move {
+                conn.interact(move |conn| {
+                    diesel::sql_query(format!(
+                        "SET statement_timeout = {}",
+                        statement_timeout * 1000
+                    ))
+                    .execute(conn)
+                })
+                .await
+                .map_err(|e| HookError::message(e.to_string()))?
+                .map_err(|e| HookError::message(e.to_string()))?;
+                Ok(())
+            })
+        }))
+        .build()?;
+
+    // fails early if the database can't be reached
+    drop(
+        pool.get()
+            .await
+            .map_err(|e| anyhow!("failed to connect to the database: {}", e))?,
+    );
+
+    Ok(web::Data::new(pool))
+}
+
+/// Connects to the database and applies pending migrations, unless
+/// `skip_migrations` is set where it only verifies that there's none.
+pub async fn establish_db_pool(config: &DatabaseConfig) -> Result<DbPool> {
+    let pool = connect_db_pool(config).await?;
+
+    // not from the pool so slow migrations won't hit the statement timeout
+    let config = config.clone();
+    tokio::task::spawn_blocking(move || establish_connection(&config)).await??;
+
+    Ok(pool)
+}
+
+/// A single connection outside of the pool for the CLI commands, which
+/// migrates the database the same way [`establish_db_pool`] does.
+pub fn establish_connection(config: &DatabaseConfig) -> Result<PgConnection> {
+    let mut conn = PgConnection::establish(&config.url)?;
+    if config.skip_migrations {
+        verify_migrations(&mut conn)?;
+    } else {
+        for version in run_migrations(&mut conn)? {
+            log::info!("Applied migration {}", version);
+        }
+    }
+    Ok(conn)
+}
+
+// arbitrary key so multiple instances starting at the same
+// time won't run the same migrations together
+static MIGRATION_LOCK_KEY: i64 = 20220923;
+
+/// Applies every pending migration, returning the applied versions
+pub fn run_migrations(conn: &mut PgConnection) -> Result<Vec<String>> {
+    log::info!("Running pending migrations");
+    diesel::sql_query(format!("SELECT pg_advisory_lock({})", MIGRATION_LOCK_KEY)).execute(conn)?;
+
+    let result = conn
+        .run_pending_migrations(MIGRATIONS)
+        .map(|versions| versions.into_iter().map(|v| v.to_string()).collect())
+        .map_err(|e| anyhow!("failed to run migrations: {}", e));
+
+    diesel::sql_query(format!("SELECT pg_advisory_unlock({})", MIGRATION_LOCK_KEY))
+        .execute(conn)?;
+
+    result
+}
+
+/// Makes sure the database has every migration `schema.rs` is
+/// generated from, without applying them.
+pub fn verify_migrations(conn: &mut PgConnection) -> Result<()> {
+    log::info!("Verifying database migrations");
+    let pending = conn
+        .pending_migrations(MIGRATIONS)
+        .map_err(|e| anyhow!("failed to get pending migrations: {}", e))?;
+
+    if !pending.is_empty() {
+        let names = pending
+            .iter()
+            .map(|v| v.name().to_string())
+            .collect::<Vec<_>>();
+
+        return Err(anyhow!(
+            "database schema is behind this build, pending migrations: {}. \
+            Run `backend-bin migrate` or unset SKIP_MIGRATIONS",
+            names.join(", ")
+        ));
+    }
+
+    // the other way around is fine as long as the old columns
+    // are still there, but it's worth knowing about
+    let known = MigrationSource::<Pg>::migrations(&MIGRATIONS)
+        .map_err(|e| anyhow!("failed to load embedded migrations: {}", e))?
+        .iter()
+        .map(|v| v.name().version().to_string())
+        .collect::<Vec<_>>();
+
+    let applied = conn
+        .applied_migrations()
+        .map_err(|e| anyhow!("failed to get applied migrations: {}", e))?;
 
-pub async fn establish_db_pool() -> Result<DbPool> {
-    log::info!("Establishing Postgres connection");
-    let database_url = std::env::var("DATABASE_URL")
-        .with_context(|| "failed to get DATABASE_URL environment variable")?;
+    for version in applied.iter().map(|v| v.to_string()) {
+        if !known.contains(&version) {
+            log::warn!("Database has migration {} unknown to this build", version);
+        }
+    }
 
-    let manager = ConnectionManager::<PgConnection>::new(database_url);
-    Ok(web::Data::new(r2d2::Pool::builder().build(manager)?))
+    Ok(())
 }
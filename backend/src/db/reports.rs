@@ -1,6 +1,7 @@
-use crate::models;
+use crate::models::{self, LetterStatus};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use uuid::Uuid;
 
@@ -18,30 +19,87 @@ pub fn get_pending(
 ) -> Result<Option<models::PendingReport>> {
     log::info!("[get] id = {}", report_id);
 
-    use crate::schema::letters::dsl as dsl_letter;
+    use crate::schema::letters;
     use crate::schema::reports::dsl::*;
 
     let report = reports
         .filter(id.eq(report_id))
-        .first::<models::Report>(conn)
+        .filter(resolved.eq(false))
+        .inner_join(letters::table)
+        .first::<(models::Report, models::Letter)>(conn)
         .optional()?;
 
-    if let Some(report) = report {
-        let letter = dsl_letter::letters
-            .filter(dsl_letter::id.eq(report.letter_id))
-            .first::<models::Letter>(conn)?;
+    Ok(report.map(|(report, letter)| models::PendingReport {
+        id: report.id,
+        email: report.email,
+        created_at: report.created_at,
+        letter,
+        type_: report.type_,
+        details: report.details,
+    }))
+}
 
-        Ok(Some(models::PendingReport {
-            id: report.id,
-            email: report.email,
-            created_at: report.created_at,
+/// What [`resolve`] changed
+pub struct Resolution {
+    pub resolved_reports: usize,
+    /// The reported letter with its new status
+    pub letter: models::Letter,
+}
+
+/// Resolves the report along with every other pending report of the same
+/// letter and gives the letter `letter_status`, all in one transaction.
+/// Returns how many reports got resolved or `None` if the report isn't pending.
+pub fn resolve(
+    conn: &mut PgConnection,
+    report_id: Uuid,
+    resolver: Uuid,
+    letter_status: LetterStatus,
+    reason: Option<&str>,
+    now: NaiveDateTime,
+) -> Result<Option<Resolution>> {
+    log::info!("[resolve] id = {}; resolver = {}", report_id, resolver);
+    use crate::schema::reports::dsl::*;
+
+    conn.transaction(|conn| {
+        let report = reports
+            .filter(id.eq(report_id))
+            .filter(resolved.eq(false))
+            .for_update()
+            .first::<models::Report>(conn)
+            .optional()?;
+
+        let reported_letter = match report.and_then(|v| v.letter_id) {
+            Some(n) => n,
+            None => return Ok(None),
+        };
+
+        let resolved_reports = diesel::update(
+            reports
+                .filter(letter_id.eq(reported_letter))
+                .filter(resolved.eq(false)),
+        )
+        .set((
+            resolved.eq(true),
+            resolved_at.eq(now),
+            resolved_by.eq(resolver),
+        ))
+        .execute(conn)?;
+
+        let letter = crate::db::letters::set_status(
+            conn,
+            reported_letter,
+            letter_status,
+            reason,
+            resolver,
+            now,
+        )?
+        .ok_or_else(|| anyhow!("reported letter {} is missing", reported_letter))?;
+
+        Ok(Some(Resolution {
+            resolved_reports,
             letter,
-            type_: report.type_,
-            details: report.details,
         }))
-    } else {
-        Ok(None)
-    }
+    })
 }
 
 pub fn get_all_pending(
@@ -55,7 +113,8 @@ pub fn get_all_pending(
     let collection: Vec<(models::Report, models::Letter)> = reports
         .filter(resolved.eq(false))
         .inner_join(letters::table)
-        .offset(offset.max(0) as i64)
+        .order((created_at.asc(), id.asc()))
+        .offset(offset as i64)
         .limit(10)
         .load::<(models::Report, models::Letter)>(conn)?;
 
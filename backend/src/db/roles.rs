@@ -0,0 +1,234 @@
+use crate::models::{self, NewRolePermission, NewUserRole, Permission};
+
+use anyhow::Result;
+use diesel::prelude::*;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Parses permissions from the database, skipping the ones
+/// this build doesn't know about (from a newer version maybe).
+fn parse_permissions(values: Vec<String>) -> Vec<Permission> {
+    values
+        .into_iter()
+        .filter_map(|v| match v.parse() {
+            Ok(n) => Some(n),
+            Err(e) => {
+                log::warn!("[roles] ignoring permission: {}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+pub fn get_all(conn: &mut PgConnection) -> Result<Vec<models::RoleInfo>> {
+    use crate::schema::role_permissions::dsl as dsl_permission;
+    use crate::schema::roles::dsl::*;
+    log::info!("getting all roles");
+
+    let collection = roles.order(name.asc()).load::<models::Role>(conn)?;
+    let mut permissions = HashMap::<String, Vec<String>>::new();
+    for (role, permission) in dsl_permission::role_permissions
+        .order(dsl_permission::permission.asc())
+        .load::<(String, String)>(conn)?
+    {
+        permissions.entry(role).or_default().push(permission);
+    }
+
+    Ok(collection
+        .into_iter()
+        .map(|role| models::RoleInfo {
+            permissions: parse_permissions(permissions.remove(&role.name).unwrap_or_default()),
+            name: role.name,
+            created_at: role.created_at,
+            requires_2fa: role.requires_2fa,
+        })
+        .collect())
+}
+
+/// Creates a role with the given permissions
+pub fn insert(
+    conn: &mut PgConnection,
+    role_name: &str,
+    permissions: &[Permission],
+    require_2fa: bool,
+) -> Result<()> {
+    use crate::schema::role_permissions::dsl::role_permissions;
+    use crate::schema::roles::dsl::*;
+    log::info!("creating new role = {}", role_name);
+
+    conn.transaction(|conn| {
+        diesel::insert_into(roles)
+            .values((name.eq(role_name), requires_2fa.eq(require_2fa)))
+            .execute(conn)?;
+
+        let values = permissions
+            .iter()
+            .map(|v| NewRolePermission {
+                role: role_name,
+                permission: v.as_str(),
+            })
+            .collect::<Vec<_>>();
+
+        diesel::insert_into(role_permissions)
+            .values(values)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+
+        Ok(())
+    })
+}
+
+/// Deletes the role and takes it away from its users, returning whether it existed
+pub fn delete(conn: &mut PgConnection, role_name: &str) -> Result<bool> {
+    use crate::schema::roles::dsl::*;
+    log::info!("deleting role = {}", role_name);
+
+    let deleted = diesel::delete(roles.filter(name.eq(role_name))).execute(conn)?;
+    Ok(deleted > 0)
+}
+
+/// Returns whether the role exists
+pub fn set_requires_2fa(
+    conn: &mut PgConnection,
+    role_name: &str,
+    require_2fa: bool,
+) -> Result<bool> {
+    use crate::schema::roles::dsl::*;
+    log::info!(
+        "setting requires_2fa of role = {} to {}",
+        role_name,
+        require_2fa
+    );
+
+    let updated = diesel::update(roles.filter(name.eq(role_name)))
+        .set(requires_2fa.eq(require_2fa))
+        .execute(conn)?;
+    Ok(updated > 0)
+}
+
+/// Gets which of the given roles don't exist
+pub fn find_missing(conn: &mut PgConnection, role_names: &[String]) -> Result<Vec<String>> {
+    use crate::schema::roles::dsl::*;
+
+    let existing = roles
+        .filter(name.eq_any(role_names))
+        .select(name)
+        .load::<String>(conn)?;
+
+    Ok(role_names
+        .iter()
+        .filter(|v| !existing.contains(v))
+        .cloned()
+        .collect())
+}
+
+/// Every permission the user has from all of their roles, roles requiring
+/// two-factor authentication only count once the user has enabled it.
+pub fn permissions_of_user(conn: &mut PgConnection, uid: Uuid) -> Result<Vec<Permission>> {
+    use crate::schema::role_permissions::dsl::*;
+    use crate::schema::roles::dsl as dsl_role;
+    use crate::schema::user_roles::dsl as dsl_user_role;
+
+    let has_2fa = crate::db::totp::is_enabled(conn, uid)?;
+    let mut query = dsl_user_role::user_roles
+        .inner_join(dsl_role::roles)
+        .inner_join(role_permissions.on(role.eq(dsl_user_role::role)))
+        .filter(dsl_user_role::user_id.eq(uid))
+        .select(permission)
+        .distinct()
+        .into_boxed();
+
+    if !has_2fa {
+        query = query.filter(dsl_role::requires_2fa.eq(false));
+    }
+
+    Ok(parse_permissions(query.load::<String>(conn)?))
+}
+
+/// Roles of the user which they don't get permissions from
+/// until they enable two-factor authentication
+pub fn roles_missing_2fa(conn: &mut PgConnection, uid: Uuid) -> Result<Vec<String>> {
+    use crate::schema::roles::dsl as dsl_role;
+    use crate::schema::user_roles::dsl::*;
+
+    if crate::db::totp::is_enabled(conn, uid)? {
+        return Ok(Vec::new());
+    }
+
+    Ok(user_roles
+        .inner_join(dsl_role::roles)
+        .filter(user_id.eq(uid))
+        .filter(dsl_role::requires_2fa.eq(true))
+        .select(role)
+        .order(role.asc())
+        .load::<String>(conn)?)
+}
+
+/// Every permission the given roles have together
+pub fn permissions_of_roles(
+    conn: &mut PgConnection,
+    role_names: &[String],
+) -> Result<Vec<Permission>> {
+    use crate::schema::role_permissions::dsl::*;
+
+    let values = role_permissions
+        .filter(role.eq_any(role_names))
+        .select(permission)
+        .distinct()
+        .load::<String>(conn)?;
+
+    Ok(parse_permissions(values))
+}
+
+pub fn roles_of_user(conn: &mut PgConnection, uid: Uuid) -> Result<Vec<String>> {
+    use crate::schema::user_roles::dsl::*;
+
+    Ok(user_roles
+        .filter(user_id.eq(uid))
+        .select(role)
+        .order(role.asc())
+        .load::<String>(conn)?)
+}
+
+pub fn roles_of_users(
+    conn: &mut PgConnection,
+    uids: &[Uuid],
+) -> Result<HashMap<Uuid, Vec<String>>> {
+    use crate::schema::user_roles::dsl::*;
+
+    let mut collection = HashMap::<Uuid, Vec<String>>::new();
+    for (uid, role_name) in user_roles
+        .filter(user_id.eq_any(uids))
+        .order(role.asc())
+        .load::<(Uuid, String)>(conn)?
+    {
+        collection.entry(uid).or_default().push(role_name);
+    }
+
+    Ok(collection)
+}
+
+/// Replaces every role of the user, the roles must exist
+pub fn set_user_roles(conn: &mut PgConnection, uid: Uuid, role_names: &[String]) -> Result<()> {
+    use crate::schema::user_roles::dsl::*;
+    log::info!("setting roles of id = {:?} to {:?}", uid, role_names);
+
+    conn.transaction(|conn| {
+        diesel::delete(user_roles.filter(user_id.eq(uid))).execute(conn)?;
+
+        let values = role_names
+            .iter()
+            .map(|v| NewUserRole {
+                user_id: uid,
+                role: v,
+            })
+            .collect::<Vec<_>>();
+
+        diesel::insert_into(user_roles)
+            .values(values)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+
+        Ok(())
+    })
+}
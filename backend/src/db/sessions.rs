@@ -0,0 +1,137 @@
+use crate::models::{self, NewSession};
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+pub fn insert(conn: &mut PgConnection, new_session: NewSession) -> Result<models::Session> {
+    use crate::schema::sessions::dsl::*;
+    log::info!("[insert] user_id = {}", new_session.user_id);
+
+    Ok(diesel::insert_into(sessions)
+        .values(new_session)
+        .get_result(conn)?)
+}
+
+/// Finds the session by either its current or previous refresh token hash
+pub fn find_by_refresh_token(
+    conn: &mut PgConnection,
+    token_hash: &str,
+) -> Result<Option<models::Session>> {
+    use crate::schema::sessions::dsl::*;
+
+    let session = sessions
+        .filter(
+            refresh_token
+                .eq(token_hash)
+                .or(previous_refresh_token.eq(token_hash)),
+        )
+        .first::<models::Session>(conn)
+        .optional()?;
+
+    Ok(session)
+}
+
+/// Gets the user of the session if the session is still active
+pub fn find_active_user(
+    conn: &mut PgConnection,
+    session_id: Uuid,
+    user_id: Uuid,
+    now: NaiveDateTime,
+) -> Result<Option<models::User>> {
+    use crate::schema::sessions::dsl as dsl_session;
+    use crate::schema::users::dsl::*;
+
+    let user = dsl_session::sessions
+        .inner_join(users)
+        .filter(dsl_session::id.eq(session_id))
+        .filter(id.eq(user_id))
+        .filter(dsl_session::revoked_at.is_null())
+        .filter(dsl_session::expires_at.gt(now))
+        .select(crate::schema::users::all_columns)
+        .first::<models::User>(conn)
+        .optional()?;
+
+    Ok(user)
+}
+
+/// Replaces the refresh token, only if it's still `old_hash` so the
+/// same refresh token can't be exchanged twice at the same time.
+pub fn rotate(
+    conn: &mut PgConnection,
+    session_id: Uuid,
+    old_hash: &str,
+    new_hash: &str,
+    new_expires_at: NaiveDateTime,
+    now: NaiveDateTime,
+) -> Result<Option<models::Session>> {
+    use crate::schema::sessions::dsl::*;
+    log::info!("[rotate] id = {}", session_id);
+
+    let session = diesel::update(
+        sessions
+            .filter(id.eq(session_id))
+            .filter(refresh_token.eq(old_hash))
+            .filter(revoked_at.is_null()),
+    )
+    .set((
+        previous_refresh_token.eq(old_hash),
+        refresh_token.eq(new_hash),
+        refreshed_at.eq(now),
+        expires_at.eq(new_expires_at),
+    ))
+    .get_result::<models::Session>(conn)
+    .optional()?;
+
+    Ok(session)
+}
+
+pub fn revoke(conn: &mut PgConnection, session_id: Uuid, now: NaiveDateTime) -> Result<()> {
+    use crate::schema::sessions::dsl::*;
+    log::info!("[revoke] id = {}", session_id);
+
+    diesel::update(
+        sessions
+            .filter(id.eq(session_id))
+            .filter(revoked_at.is_null()),
+    )
+    .set(revoked_at.eq(now))
+    .execute(conn)?;
+
+    Ok(())
+}
+
+/// Revokes every session of the user, returning how many are revoked
+pub fn revoke_all(conn: &mut PgConnection, uid: Uuid, now: NaiveDateTime) -> Result<usize> {
+    use crate::schema::sessions::dsl::*;
+    log::info!("[revoke_all] user_id = {}", uid);
+
+    Ok(diesel::update(
+        sessions
+            .filter(user_id.eq(uid))
+            .filter(revoked_at.is_null()),
+    )
+    .set(revoked_at.eq(now))
+    .execute(conn)?)
+}
+
+/// Revokes every session of the user other than `keep`, returning how many are revoked
+pub fn revoke_all_except(
+    conn: &mut PgConnection,
+    uid: Uuid,
+    keep: Uuid,
+    now: NaiveDateTime,
+) -> Result<usize> {
+    use crate::schema::sessions::dsl::*;
+    log::info!("[revoke_all_except] user_id = {}; keep = {}", uid, keep);
+
+    Ok(diesel::update(
+        sessions
+            .filter(user_id.eq(uid))
+            .filter(id.ne(keep))
+            .filter(revoked_at.is_null()),
+    )
+    .set(revoked_at.eq(now))
+    .execute(conn)?)
+}
@@ -3,15 +3,46 @@ use crate::models;
 use anyhow::Result;
 use diesel::prelude::*;
 
-pub fn is_available(conn: &mut PgConnection) -> Result<bool> {
+pub fn get(conn: &mut PgConnection) -> Result<Option<models::State>> {
     use crate::schema::states::dsl::*;
 
     log::info!("fetching state");
-    let value = states
-        .first::<models::State>(conn)
-        .optional()?
-        .map(|v| v.available)
-        .unwrap_or_default();
+    Ok(states.first::<models::State>(conn).optional()?)
+}
+
+pub fn set_premoderation(conn: &mut PgConnection, enabled: bool) -> Result<models::State> {
+    use crate::schema::states::dsl::*;
+
+    log::info!("[set_premoderation] premoderation = {}", enabled);
+
+    // same as `set_available`, a missing row means submissions are closed
+    Ok(diesel::insert_into(states)
+        .values((id.eq(1), available.eq(false), premoderation.eq(enabled)))
+        .on_conflict(id)
+        .do_update()
+        .set(premoderation.eq(enabled))
+        .get_result(conn)?)
+}
+
+pub fn set_available(
+    conn: &mut PgConnection,
+    new_state: models::UpdateState,
+) -> Result<models::State> {
+    use crate::schema::states::dsl::*;
+
+    log::info!(
+        "[set_available] available = {}; opens_at = {:?}; closes_at = {:?}",
+        new_state.available,
+        new_state.opens_at,
+        new_state.closes_at
+    );
 
-    Ok(value)
+    // there's only one row in the table but it may be missing
+    // if someone decided to clear it manually
+    Ok(diesel::insert_into(states)
+        .values((id.eq(1), &new_state))
+        .on_conflict(id)
+        .do_update()
+        .set(&new_state)
+        .get_result(conn)?)
 }
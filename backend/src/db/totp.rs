@@ -0,0 +1,173 @@
+use crate::models::{self, NewRecoveryCode};
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+pub fn find(conn: &mut PgConnection, uid: Uuid) -> Result<Option<models::UserTotp>> {
+    use crate::schema::user_totp::dsl::*;
+
+    let totp = user_totp
+        .filter(user_id.eq(uid))
+        .first::<models::UserTotp>(conn)
+        .optional()?;
+
+    Ok(totp)
+}
+
+pub fn is_enabled(conn: &mut PgConnection, uid: Uuid) -> Result<bool> {
+    Ok(find(conn, uid)?.map(|v| v.is_enabled()).unwrap_or_default())
+}
+
+/// Stores a new secret waiting to be confirmed, replacing the one from an
+/// unfinished enrollment. Returns false if the user already has it enabled.
+pub fn start_enrollment(
+    conn: &mut PgConnection,
+    uid: Uuid,
+    new_secret: &str,
+    now: NaiveDateTime,
+) -> Result<bool> {
+    use crate::schema::user_totp::dsl::*;
+    log::info!("[start_enrollment] user_id = {}", uid);
+
+    conn.transaction(|conn| {
+        let existing = user_totp
+            .filter(user_id.eq(uid))
+            .for_update()
+            .first::<models::UserTotp>(conn)
+            .optional()?;
+
+        match existing {
+            Some(n) if n.is_enabled() => return Ok(false),
+            Some(_) => {
+                diesel::update(user_totp.filter(user_id.eq(uid)))
+                    .set((secret.eq(new_secret), created_at.eq(now)))
+                    .execute(conn)?;
+            }
+            None => {
+                diesel::insert_into(user_totp)
+                    .values((user_id.eq(uid), secret.eq(new_secret), created_at.eq(now)))
+                    .execute(conn)?;
+            }
+        }
+
+        Ok(true)
+    })
+}
+
+/// Enables it once the user confirms the first code, replacing
+/// any recovery codes left from a previous enrollment.
+pub fn enable(
+    conn: &mut PgConnection,
+    uid: Uuid,
+    step: i64,
+    recovery_code_hashes: &[String],
+    now: NaiveDateTime,
+) -> Result<bool> {
+    use crate::schema::user_totp::dsl::*;
+    log::info!("[enable] user_id = {}", uid);
+
+    conn.transaction(|conn| {
+        let updated = diesel::update(
+            user_totp
+                .filter(user_id.eq(uid))
+                .filter(enabled_at.is_null()),
+        )
+        .set((enabled_at.eq(now), last_used_step.eq(step)))
+        .execute(conn)?;
+
+        if updated == 0 {
+            return Ok(false);
+        }
+
+        replace_recovery_codes(conn, uid, recovery_code_hashes)?;
+        Ok(true)
+    })
+}
+
+pub fn disable(conn: &mut PgConnection, uid: Uuid) -> Result<bool> {
+    use crate::schema::recovery_codes::dsl as dsl_code;
+    use crate::schema::user_totp::dsl::*;
+    log::info!("[disable] user_id = {}", uid);
+
+    conn.transaction(|conn| {
+        diesel::delete(dsl_code::recovery_codes.filter(dsl_code::user_id.eq(uid))).execute(conn)?;
+        let deleted = diesel::delete(user_totp.filter(user_id.eq(uid))).execute(conn)?;
+        Ok(deleted > 0)
+    })
+}
+
+/// Marks the time step as used, only if no later step is used yet
+/// so the same code can't be used twice at the same time.
+pub fn use_step(conn: &mut PgConnection, uid: Uuid, step: i64) -> Result<bool> {
+    use crate::schema::user_totp::dsl::*;
+
+    let updated = diesel::update(
+        user_totp
+            .filter(user_id.eq(uid))
+            .filter(enabled_at.is_not_null())
+            .filter(last_used_step.is_null().or(last_used_step.lt(step))),
+    )
+    .set(last_used_step.eq(step))
+    .execute(conn)?;
+
+    Ok(updated > 0)
+}
+
+pub fn replace_recovery_codes(
+    conn: &mut PgConnection,
+    uid: Uuid,
+    code_hashes: &[String],
+) -> Result<()> {
+    use crate::schema::recovery_codes::dsl::*;
+    log::info!("[replace_recovery_codes] user_id = {}", uid);
+
+    conn.transaction(|conn| {
+        diesel::delete(recovery_codes.filter(user_id.eq(uid))).execute(conn)?;
+
+        let values = code_hashes
+            .iter()
+            .map(|v| NewRecoveryCode {
+                user_id: uid,
+                code_hash: v,
+            })
+            .collect::<Vec<_>>();
+
+        diesel::insert_into(recovery_codes)
+            .values(values)
+            .execute(conn)?;
+        Ok(())
+    })
+}
+
+/// Uses up the recovery code, returning whether it's valid
+pub fn use_recovery_code(
+    conn: &mut PgConnection,
+    uid: Uuid,
+    hash: &str,
+    now: NaiveDateTime,
+) -> Result<bool> {
+    use crate::schema::recovery_codes::dsl::*;
+
+    let updated = diesel::update(
+        recovery_codes
+            .filter(user_id.eq(uid))
+            .filter(code_hash.eq(hash))
+            .filter(used_at.is_null()),
+    )
+    .set(used_at.eq(now))
+    .execute(conn)?;
+
+    Ok(updated > 0)
+}
+
+pub fn count_unused_recovery_codes(conn: &mut PgConnection, uid: Uuid) -> Result<i64> {
+    use crate::schema::recovery_codes::dsl::*;
+
+    Ok(recovery_codes
+        .filter(user_id.eq(uid))
+        .filter(used_at.is_null())
+        .count()
+        .get_result(conn)?)
+}
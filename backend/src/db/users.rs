@@ -2,8 +2,26 @@ use crate::models::{self, NewUser};
 
 use anyhow::Result;
 use diesel::prelude::*;
+use diesel::result::DatabaseErrorKind;
 use uuid::Uuid;
 
+// unique constraint on `users.name`
+static NAME_CONSTRAINT: &str = "users_name_key";
+
+/// Whether inserting or renaming failed since the username is already taken.
+///
+/// Postgres aborts the transaction on the violation, so
+/// check it once the transaction is rolled back.
+pub fn is_name_taken(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<diesel::result::Error>(),
+            Some(diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, info))
+                if info.constraint_name() == Some(NAME_CONSTRAINT)
+        )
+    })
+}
+
 pub fn insert(conn: &mut PgConnection, new_user: NewUser) -> Result<models::User> {
     use crate::schema::users::dsl::*;
     log::info!("creating new user = {}", new_user.name);
@@ -13,6 +31,19 @@ pub fn insert(conn: &mut PgConnection, new_user: NewUser) -> Result<models::User
         .get_result(conn)?)
 }
 
+/// Creates the user along with their roles, nothing is created if any role doesn't exist
+pub fn insert_with_roles(
+    conn: &mut PgConnection,
+    new_user: NewUser,
+    roles: &[String],
+) -> Result<models::User> {
+    conn.transaction(|conn| {
+        let user = insert(conn, new_user)?;
+        crate::db::roles::set_user_roles(conn, user.id, roles)?;
+        Ok(user)
+    })
+}
+
 pub fn find_by_id(conn: &mut PgConnection, uuid: &Uuid) -> Result<Option<models::User>> {
     use crate::schema::users::dsl::*;
     log::info!("getting from id = {:?}", uuid);
@@ -41,3 +72,71 @@ pub fn find_by_username(
 
     Ok(user)
 }
+
+pub fn update_password(conn: &mut PgConnection, user_id: Uuid, new_password: &str) -> Result<()> {
+    use crate::schema::users::dsl::*;
+    log::info!("updating password of id = {:?}", user_id);
+
+    diesel::update(users.filter(id.eq(user_id)))
+        .set(password.eq(new_password))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Replaces the password and logs out every other session of the user,
+/// returning how many sessions are revoked.
+pub fn change_password(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    new_password: &str,
+    current_session: Uuid,
+) -> Result<usize> {
+    conn.transaction(|conn| {
+        update_password(conn, user_id, new_password)?;
+        crate::db::sessions::revoke_all_except(
+            conn,
+            user_id,
+            current_session,
+            chrono::Utc::now().naive_utc(),
+        )
+    })
+}
+
+pub fn get_all(conn: &mut PgConnection, limit: usize, offset: usize) -> Result<Vec<models::User>> {
+    use crate::schema::users::dsl::*;
+    log::info!("getting all users");
+
+    let collection = users
+        .order(created_at.asc())
+        .offset(offset as i64)
+        .limit(limit as i64)
+        .load::<models::User>(conn)?;
+
+    Ok(collection)
+}
+
+pub fn rename(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    new_name: &str,
+) -> Result<Option<models::User>> {
+    use crate::schema::users::dsl::*;
+    log::info!("renaming id = {:?}", user_id);
+
+    let user = diesel::update(users.filter(id.eq(user_id)))
+        .set(name.eq(new_name))
+        .get_result::<models::User>(conn)
+        .optional()?;
+
+    Ok(user)
+}
+
+/// Deletes the user along with their sessions, returning whether it existed
+pub fn delete(conn: &mut PgConnection, user_id: Uuid) -> Result<bool> {
+    use crate::schema::users::dsl::*;
+    log::info!("deleting id = {:?}", user_id);
+
+    let deleted = diesel::delete(users.filter(id.eq(user_id))).execute(conn)?;
+    Ok(deleted > 0)
+}
@@ -0,0 +1,163 @@
+use crate::models::prelude::*;
+
+use serde_json::Value;
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Something staff did which is kept in the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AuditAction {
+    ResolveReport,
+    RevokeReport,
+    RestoreLetter,
+    ApproveLetter,
+    RejectLetter,
+    CreateUser,
+    RenameUser,
+    DeleteUser,
+    SetUserRoles,
+    ResetUserTotp,
+    CreateInvite,
+    DeleteInvite,
+    CreateRole,
+    DeleteRole,
+    SetRoleRequires2fa,
+    SetAvailability,
+    SetPremoderation,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 17] = [
+        Self::ResolveReport,
+        Self::RevokeReport,
+        Self::RestoreLetter,
+        Self::ApproveLetter,
+        Self::RejectLetter,
+        Self::CreateUser,
+        Self::RenameUser,
+        Self::DeleteUser,
+        Self::SetUserRoles,
+        Self::ResetUserTotp,
+        Self::CreateInvite,
+        Self::DeleteInvite,
+        Self::CreateRole,
+        Self::DeleteRole,
+        Self::SetRoleRequires2fa,
+        Self::SetAvailability,
+        Self::SetPremoderation,
+    ];
+
+    /// How it's stored in `audit_log.action`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::ResolveReport => "report:resolve",
+            Self::RevokeReport => "report:revoke",
+            Self::RestoreLetter => "letter:restore",
+            Self::ApproveLetter => "letter:approve",
+            Self::RejectLetter => "letter:reject",
+            Self::CreateUser => "user:create",
+            Self::RenameUser => "user:rename",
+            Self::DeleteUser => "user:delete",
+            Self::SetUserRoles => "user:set_roles",
+            Self::ResetUserTotp => "user:reset_totp",
+            Self::CreateInvite => "invite:create",
+            Self::DeleteInvite => "invite:delete",
+            Self::CreateRole => "role:create",
+            Self::DeleteRole => "role:delete",
+            Self::SetRoleRequires2fa => "role:set_requires_2fa",
+            Self::SetAvailability => "state:set_availability",
+            Self::SetPremoderation => "state:set_premoderation",
+        }
+    }
+}
+
+impl Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AuditAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|v| v.as_str() == s)
+            .ok_or_else(|| anyhow::anyhow!("unknown audit action {:?}", s))
+    }
+}
+
+impl TryFrom<String> for AuditAction {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Serialize for AuditAction {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for AuditAction {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Serialize, Queryable)]
+pub struct AuditEntry {
+    pub id: Uuid,
+    pub created_at: NaiveDateTime,
+    /// Missing for actions done from the command line
+    pub actor_id: Option<Uuid>,
+    #[diesel(deserialize_as = String)]
+    pub action: AuditAction,
+    pub letter_id: Option<Uuid>,
+    pub report_id: Option<Uuid>,
+    pub user_id: Option<Uuid>,
+    /// What was changed, as it was before and after the action
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = audit_log)]
+pub struct NewAuditEntry {
+    pub actor_id: Option<Uuid>,
+    pub action: &'static str,
+    pub letter_id: Option<Uuid>,
+    pub report_id: Option<Uuid>,
+    pub user_id: Option<Uuid>,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+impl NewAuditEntry {
+    pub fn new(actor_id: Option<Uuid>, action: AuditAction) -> Self {
+        Self {
+            actor_id,
+            action: action.as_str(),
+            letter_id: None,
+            report_id: None,
+            user_id: None,
+            before: None,
+            after: None,
+        }
+    }
+
+    /// Keeps what's changed as JSON, in the same shape it's sent to clients
+    pub fn before(mut self, value: &impl Serialize) -> anyhow::Result<Self> {
+        self.before = Some(serde_json::to_value(value)?);
+        Ok(self)
+    }
+
+    pub fn after(mut self, value: &impl Serialize) -> anyhow::Result<Self> {
+        self.after = Some(serde_json::to_value(value)?);
+        Ok(self)
+    }
+}
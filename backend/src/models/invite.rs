@@ -0,0 +1,47 @@
+use crate::models::prelude::*;
+
+use chrono::Duration;
+
+pub static INVITE_EXPIRY_DURATION: i64 = 60 * 60 * 24 * 7;
+pub static MAX_INVITE_EXPIRY_DURATION: i64 = 60 * 60 * 24 * 30;
+
+/// Single use code letting someone register, only its hash is stored
+#[derive(Debug, Serialize, Queryable, Identifiable)]
+pub struct Invite {
+    pub id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub created_by: Option<Uuid>,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+    pub used_by: Option<Uuid>,
+    #[serde(skip)]
+    pub code_hash: String,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = invites)]
+pub struct NewInvite<'a> {
+    pub created_by: Option<Uuid>,
+    pub expires_at: NaiveDateTime,
+    pub code_hash: &'a str,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = invite_roles)]
+pub struct NewInviteRole<'a> {
+    pub invite_id: Uuid,
+    pub role: &'a str,
+}
+
+/// An invite along with the roles given to whoever uses it
+#[derive(Debug, Serialize)]
+pub struct InviteInfo {
+    #[serde(flatten)]
+    pub invite: Invite,
+    pub roles: Vec<String>,
+}
+
+/// When an invite created at `now` lasting for `expires_in` seconds expires
+pub fn invite_expiry(now: NaiveDateTime, expires_in: i64) -> NaiveDateTime {
+    now + Duration::seconds(expires_in)
+}
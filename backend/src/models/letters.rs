@@ -1,12 +1,99 @@
 use crate::models::prelude::*;
 
-#[derive(Debug, Deserialize, Serialize, Queryable, Insertable, Identifiable)]
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Whether a letter is shown publicly, set by moderators
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LetterStatus {
+    Visible,
+    /// Kept out of public view for now, e.g. while looking into it
+    Hidden,
+    /// Taken down for breaking the rules
+    Removed,
+    /// Waiting for a moderator to look at it first
+    PendingReview,
+}
+
+impl LetterStatus {
+    pub const ALL: [LetterStatus; 4] = [
+        Self::Visible,
+        Self::Hidden,
+        Self::Removed,
+        Self::PendingReview,
+    ];
+
+    /// How it's stored in `letters.status`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Visible => "visible",
+            Self::Hidden => "hidden",
+            Self::Removed => "removed",
+            Self::PendingReview => "pending_review",
+        }
+    }
+}
+
+impl Display for LetterStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for LetterStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|v| v.as_str() == s)
+            .ok_or_else(|| anyhow::anyhow!("unknown letter status {:?}", s))
+    }
+}
+
+impl TryFrom<String> for LetterStatus {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Serialize for LetterStatus {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for LetterStatus {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Queryable, Identifiable)]
 pub struct Letter {
     pub id: Uuid,
     pub created_at: NaiveDateTime,
     pub author: String,
     pub message: String,
     pub secret: bool,
+    #[diesel(deserialize_as = String)]
+    pub status: LetterStatus,
+    pub moderation_reason: Option<String>,
+    pub moderated_by: Option<Uuid>,
+    pub moderated_at: Option<NaiveDateTime>,
+}
+
+/// What everyone can see of a letter, leaving out moderation details
+#[derive(Debug, Serialize, Queryable)]
+pub struct PublicLetter {
+    pub id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub author: String,
+    pub message: String,
+    pub secret: bool,
 }
 
 #[derive(Debug, Serialize, Insertable)]
@@ -15,4 +102,5 @@ pub struct NewLetter<'a> {
     pub author: &'a str,
     pub message: &'a str,
     pub secret: bool,
+    pub status: &'a str,
 }
@@ -0,0 +1,163 @@
+use crate::config::LoginThrottleConfig;
+use crate::models::prelude::*;
+
+use chrono::Duration;
+
+/// Failed logins are counted separately for usernames and IP addresses
+pub fn user_throttle_key(username: &str) -> String {
+    format!("user:{}", username)
+}
+
+pub fn ip_throttle_key(ip: impl std::fmt::Display) -> String {
+    format!("ip:{}", ip)
+}
+
+#[derive(Debug, Queryable)]
+pub struct LoginFailure {
+    pub key: String,
+    /// Since `first_failed_at`, reset on every lockout
+    pub failures: i32,
+    pub first_failed_at: NaiveDateTime,
+    pub last_failed_at: NaiveDateTime,
+    pub locked_until: Option<NaiveDateTime>,
+    pub lockouts: i32,
+}
+
+impl LoginFailure {
+    /// Counts a failed login at `now`, older failures are forgotten
+    /// once they're out of the window.
+    pub fn count_failure(&mut self, config: &LoginThrottleConfig, now: NaiveDateTime) {
+        let max_lockout = Duration::seconds(config.max_lockout_secs as i64);
+        let window = Duration::seconds(config.failure_window_secs as i64);
+        if self.last_failed_at + max_lockout < now {
+            // quiet for long enough, previous lockouts are forgiven
+            self.failures = 0;
+            self.lockouts = 0;
+            self.first_failed_at = now;
+        } else if self.first_failed_at + window < now {
+            self.failures = 0;
+            self.first_failed_at = now;
+        }
+
+        self.failures += 1;
+        self.last_failed_at = now;
+    }
+
+    /// Locks the key starting over the count, each lockout lasts twice
+    /// as long as the previous one up to `max_lockout_secs`.
+    pub fn lock(&mut self, config: &LoginThrottleConfig, now: NaiveDateTime) -> NaiveDateTime {
+        let secs = config
+            .lockout_secs
+            .saturating_mul(1u64.checked_shl(self.lockouts as u32).unwrap_or(u64::MAX))
+            .min(config.max_lockout_secs);
+        let until = now + Duration::seconds(secs as i64);
+
+        self.failures = 0;
+        self.first_failed_at = now;
+        self.lockouts += 1;
+        self.locked_until = Some(until);
+        until
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = login_lockouts)]
+pub struct NewLoginLockout<'a> {
+    pub key: &'a str,
+    pub ip: Option<&'a str>,
+    pub failures: i32,
+    pub locked_until: NaiveDateTime,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: i64) -> NaiveDateTime {
+        NaiveDateTime::from_timestamp_opt(1_000_000 + secs, 0).unwrap()
+    }
+
+    fn config() -> LoginThrottleConfig {
+        LoginThrottleConfig {
+            max_user_failures: 3,
+            max_ip_failures: 10,
+            failure_window_secs: 60,
+            lockout_secs: 10,
+            max_lockout_secs: 100,
+        }
+    }
+
+    /// Like a new row, before its first failure is counted
+    fn entry(now: NaiveDateTime) -> LoginFailure {
+        LoginFailure {
+            key: user_throttle_key("user"),
+            failures: 0,
+            first_failed_at: now,
+            last_failed_at: now,
+            locked_until: None,
+            lockouts: 0,
+        }
+    }
+
+    #[test]
+    fn counts_within_the_window() {
+        let config = config();
+        let mut entry = entry(at(0));
+        for secs in [0, 30, 60] {
+            entry.count_failure(&config, at(secs));
+        }
+        assert_eq!(entry.failures, 3);
+
+        // the first failure is out of the window now
+        entry.count_failure(&config, at(61));
+        assert_eq!(entry.failures, 1);
+        assert_eq!(entry.first_failed_at, at(61));
+    }
+
+    #[test]
+    fn lockouts_double_up_to_the_max() {
+        let config = config();
+        let mut entry = entry(at(0));
+        let mut now = 0;
+        let mut lockouts = Vec::new();
+        for _ in 0..6 {
+            entry.count_failure(&config, at(now));
+            let until = entry.lock(&config, at(now));
+            assert_eq!(entry.failures, 0);
+            assert_eq!(entry.locked_until, Some(until));
+
+            lockouts.push((until - at(now)).num_seconds());
+            now = (until - at(0)).num_seconds();
+        }
+        assert_eq!(lockouts, [10, 20, 40, 80, 100, 100]);
+    }
+
+    #[test]
+    fn backoff_never_overflows() {
+        let config = config();
+        for lockouts in [63, 64, 1000] {
+            let mut entry = entry(at(0));
+            entry.lockouts = lockouts;
+            assert_eq!(entry.lock(&config, at(0)), at(100));
+        }
+    }
+
+    #[test]
+    fn forgives_lockouts_after_being_quiet() {
+        let config = config();
+        let mut entry = entry(at(0));
+        entry.count_failure(&config, at(0));
+        entry.lock(&config, at(0));
+        entry.lock(&config, at(0));
+        assert_eq!(entry.lockouts, 2);
+
+        // still within the longest lockout of the last failure
+        entry.count_failure(&config, at(100));
+        assert_eq!(entry.lockouts, 2);
+
+        entry.count_failure(&config, at(201));
+        assert_eq!(entry.lockouts, 0);
+        assert_eq!(entry.failures, 1);
+        assert_eq!(entry.lock(&config, at(201)), at(211));
+    }
+}
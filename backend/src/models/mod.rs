@@ -1,13 +1,25 @@
 pub(crate) mod prelude;
 
+mod audit;
+mod invite;
 mod letters;
+mod login_failure;
 mod report;
+mod role;
+mod session;
 mod state;
+mod totp;
 mod user;
 mod user_token;
 
+pub use audit::*;
+pub use invite::*;
 pub use letters::*;
+pub use login_failure::*;
 pub use report::*;
+pub use role::*;
+pub use session::*;
 pub use state::*;
+pub use totp::*;
 pub use user::*;
 pub use user_token::*;
@@ -1,5 +1,5 @@
 pub use chrono::NaiveDateTime;
-pub use diesel::{Identifiable, Insertable, Queryable};
+pub use diesel::{AsChangeset, Identifiable, Insertable, Queryable};
 pub use serde::{Deserialize, Serialize};
 pub use serde_repr::{Deserialize_repr, Serialize_repr};
 pub use uuid::Uuid;
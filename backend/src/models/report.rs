@@ -45,9 +45,12 @@ pub struct Report {
     pub id: Uuid,
     pub email: String,
     pub created_at: NaiveDateTime,
-    pub letter_id: Uuid,
+    /// Missing once the letter is deleted, which moderators can't do
+    pub letter_id: Option<Uuid>,
     #[serde(rename = "type")]
     pub type_: i32,
     pub details: String,
     pub resolved: bool,
+    pub resolved_at: Option<NaiveDateTime>,
+    pub resolved_by: Option<Uuid>,
 }
@@ -0,0 +1,111 @@
+use crate::models::prelude::*;
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Something a role allows its users to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Permission {
+    ReadSecretLetters,
+    ReadReports,
+    ResolveReports,
+    ManageUsers,
+    ToggleState,
+    ReadAuditLog,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 6] = [
+        Self::ReadSecretLetters,
+        Self::ReadReports,
+        Self::ResolveReports,
+        Self::ManageUsers,
+        Self::ToggleState,
+        Self::ReadAuditLog,
+    ];
+
+    /// How it's stored in `role_permissions`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::ReadSecretLetters => "letters:read_secret",
+            Self::ReadReports => "reports:read",
+            Self::ResolveReports => "reports:resolve",
+            Self::ManageUsers => "users:manage",
+            Self::ToggleState => "state:toggle",
+            Self::ReadAuditLog => "audit_log:read",
+        }
+    }
+
+    /// Used in "Not authorized to ..." errors
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::ReadSecretLetters => "read secret letters",
+            Self::ReadReports => "view reports",
+            Self::ResolveReports => "resolve reports",
+            Self::ManageUsers => "manage users",
+            Self::ToggleState => "open or close submissions",
+            Self::ReadAuditLog => "view the audit log",
+        }
+    }
+}
+
+impl Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Permission {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|v| v.as_str() == s)
+            .ok_or_else(|| anyhow::anyhow!("unknown permission {:?}", s))
+    }
+}
+
+impl Serialize for Permission {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Permission {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Serialize, Queryable)]
+pub struct Role {
+    pub name: String,
+    pub created_at: NaiveDateTime,
+    /// Its permissions are only given to users with two-factor authentication
+    pub requires_2fa: bool,
+}
+
+/// A role along with what it allows
+#[derive(Debug, Serialize)]
+pub struct RoleInfo {
+    pub name: String,
+    pub created_at: NaiveDateTime,
+    pub requires_2fa: bool,
+    pub permissions: Vec<Permission>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = user_roles)]
+pub struct NewUserRole<'a> {
+    pub user_id: Uuid,
+    pub role: &'a str,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = role_permissions)]
+pub struct NewRolePermission<'a> {
+    pub role: &'a str,
+    pub permission: &'a str,
+}
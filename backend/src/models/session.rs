@@ -0,0 +1,40 @@
+use crate::models::prelude::*;
+
+use chrono::Duration;
+
+pub static REFRESH_TOKEN_EXPIRY_DURATION: i64 = 60 * 60 * 24 * 30;
+
+/// A logged in device, its refresh token is replaced every time it's used
+#[derive(Debug, Queryable, Identifiable)]
+pub struct Session {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub refreshed_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+    /// SHA-256 hash of the current refresh token
+    pub refresh_token: String,
+    /// Hash of the refresh token before the last rotation,
+    /// kept to detect stolen refresh tokens being reused.
+    pub previous_refresh_token: Option<String>,
+}
+
+impl Session {
+    pub fn is_active_at(&self, now: NaiveDateTime) -> bool {
+        self.revoked_at.is_none() && now < self.expires_at
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = sessions)]
+pub struct NewSession<'a> {
+    pub user_id: Uuid,
+    pub expires_at: NaiveDateTime,
+    pub refresh_token: &'a str,
+}
+
+/// When a refresh token given at `now` expires
+pub fn refresh_token_expiry(now: NaiveDateTime) -> NaiveDateTime {
+    now + Duration::seconds(REFRESH_TOKEN_EXPIRY_DURATION)
+}
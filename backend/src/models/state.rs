@@ -1,7 +1,66 @@
 use crate::models::prelude::*;
 
+use chrono::Duration;
+
 #[derive(Debug, Deserialize, Serialize, Queryable, Identifiable)]
 pub struct State {
     pub id: i32,
     pub available: bool,
+    pub closes_at: Option<NaiveDateTime>,
+    pub closed_message: Option<String>,
+    pub opens_at: Option<NaiveDateTime>,
+    /// New letters have to be approved by a moderator before being public
+    pub premoderation: bool,
+}
+
+impl State {
+    /// Whether submissions are accepted at the given time (in UTC)
+    pub fn is_available_at(&self, now: NaiveDateTime) -> bool {
+        self.available
+            && self.opens_at.map(|v| now >= v).unwrap_or(true)
+            && self.closes_at.map(|v| now < v).unwrap_or(true)
+    }
+
+    /// Time left until submissions open, if they're scheduled to
+    pub fn opens_in(&self, now: NaiveDateTime) -> Option<Duration> {
+        self.opens_at
+            .filter(|v| self.available && now < *v)
+            .map(|v| v - now)
+    }
+
+    /// Time left until submissions close, if they're currently open
+    /// and scheduled to close
+    pub fn closes_in(&self, now: NaiveDateTime) -> Option<Duration> {
+        self.closes_at
+            .filter(|_| self.is_available_at(now))
+            .map(|v| v - now)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Insertable, AsChangeset)]
+#[diesel(table_name = states, treat_none_as_null = true)]
+pub struct UpdateState {
+    pub available: bool,
+    pub opens_at: Option<NaiveDateTime>,
+    pub closes_at: Option<NaiveDateTime>,
+    pub closed_message: Option<String>,
+}
+
+impl UpdateState {
+    /// Checks whether the schedule makes sense at the given time (in UTC)
+    pub fn validate(&self, now: NaiveDateTime) -> Result<(), &'static str> {
+        if let Some(closes_at) = self.closes_at {
+            if closes_at <= now {
+                return Err("closes_at must be in the future");
+            }
+        }
+
+        if let (Some(opens_at), Some(closes_at)) = (self.opens_at, self.closes_at) {
+            if opens_at >= closes_at {
+                return Err("opens_at must be before closes_at");
+            }
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,26 @@
+use crate::models::prelude::*;
+
+#[derive(Debug, Queryable, Identifiable)]
+#[diesel(table_name = user_totp, primary_key(user_id))]
+pub struct UserTotp {
+    pub user_id: Uuid,
+    pub created_at: NaiveDateTime,
+    /// Base32 encoded like in the provisioning URI
+    pub secret: String,
+    /// Not set while the user hasn't confirmed a code yet
+    pub enabled_at: Option<NaiveDateTime>,
+    pub last_used_step: Option<i64>,
+}
+
+impl UserTotp {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled_at.is_some()
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = recovery_codes)]
+pub struct NewRecoveryCode<'a> {
+    pub user_id: Uuid,
+    pub code_hash: &'a str,
+}
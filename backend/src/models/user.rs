@@ -7,8 +7,6 @@ pub struct User {
     pub created_at: NaiveDateTime,
     pub name: String,
     pub password: String,
-    pub moderator: Option<bool>,
-    pub viewer: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Queryable, Insertable)]
@@ -17,3 +15,23 @@ pub struct NewUser<'a> {
     pub name: &'a str,
     pub password: &'a str,
 }
+
+/// What is shown about a user to admins, without the password hash
+#[derive(Debug, Serialize)]
+pub struct UserInfo {
+    pub id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub name: String,
+    pub roles: Vec<String>,
+}
+
+impl UserInfo {
+    pub fn new(user: User, roles: Vec<String>) -> Self {
+        Self {
+            id: user.id,
+            created_at: user.created_at,
+            name: user.name,
+            roles,
+        }
+    }
+}
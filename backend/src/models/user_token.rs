@@ -6,26 +6,35 @@ use chrono::{Duration, Local};
 use jsonwebtoken::{decode, encode};
 use jsonwebtoken::{DecodingKey, EncodingKey, Header, TokenData, Validation};
 
-pub static TOKEN_EXPIRY_DURATION: i64 = 60 * 60 * 24;
+/// Access tokens are short lived since they're only revoked along with
+/// their session, refresh tokens are used to get a new one.
+pub static TOKEN_EXPIRY_DURATION: i64 = 60 * 15;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct UserToken {
     pub sub: String,
+    /// Id of the session the token is issued from
+    pub sid: String,
     pub exp: usize,
 }
 
 impl UserToken {
-    pub fn with_user_id(user_id: impl AsRef<str>) -> Self {
+    pub fn with_user_id(user_id: impl AsRef<str>, session_id: impl AsRef<str>) -> Self {
         Self {
             sub: user_id.as_ref().to_string(),
+            sid: session_id.as_ref().to_string(),
             exp: (Local::now() + Duration::seconds(TOKEN_EXPIRY_DURATION)).timestamp() as usize,
         }
     }
 
-    pub fn generate_token(user_id: impl AsRef<str>, key: &[u8]) -> Result<String> {
-        let info = UserToken::with_user_id(user_id);
+    pub fn generate_token(
+        user_id: impl AsRef<str>,
+        session_id: impl AsRef<str>,
+        key: &[u8],
+    ) -> Result<String> {
+        let info = UserToken::with_user_id(user_id, session_id);
         let key = EncodingKey::from_secret(key);
-        Ok(encode(&Header::default(), &info, &key).with_context(|| "failed to generate token")?)
+        encode(&Header::default(), &info, &key).with_context(|| "failed to generate token")
     }
 }
 
@@ -38,3 +47,47 @@ impl UserToken {
         )?)
     }
 }
+
+/// How long users have to enter their second factor after the password
+pub static TWO_FACTOR_CHALLENGE_EXPIRY_DURATION: i64 = 60 * 5;
+
+static TWO_FACTOR_CHALLENGE_KIND: &str = "totp";
+
+/// Proof that the password is already verified, exchanged for a session
+/// along with the second factor. It can't be used as an access token
+/// (or the other way around) since their claims are different.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TwoFactorChallenge {
+    pub sub: String,
+    pub challenge: String,
+    pub exp: usize,
+}
+
+impl TwoFactorChallenge {
+    pub fn generate_token(user_id: impl AsRef<str>, key: &[u8]) -> Result<String> {
+        let info = Self {
+            sub: user_id.as_ref().to_string(),
+            challenge: TWO_FACTOR_CHALLENGE_KIND.to_string(),
+            exp: (Local::now() + Duration::seconds(TWO_FACTOR_CHALLENGE_EXPIRY_DURATION))
+                .timestamp() as usize,
+        };
+        let key = EncodingKey::from_secret(key);
+        encode(&Header::default(), &info, &key).with_context(|| "failed to generate challenge")
+    }
+
+    pub fn decode_token(token: impl AsRef<str>, key: &[u8]) -> Result<Self> {
+        let data = decode::<Self>(
+            token.as_ref(),
+            &DecodingKey::from_secret(key),
+            &Validation::default(),
+        )?;
+
+        if data.claims.challenge != TWO_FACTOR_CHALLENGE_KIND {
+            return Err(anyhow::anyhow!(
+                "unknown challenge {:?}",
+                data.claims.challenge
+            ));
+        }
+        Ok(data.claims)
+    }
+}
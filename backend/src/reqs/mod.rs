@@ -1,2 +1,18 @@
+use actix_web::http::header::{self, HeaderMap};
+
+pub mod permission;
 pub mod register;
 pub mod user;
+
+pub static TOKEN_COOKIE: &str = "token";
+pub static REFRESH_TOKEN_COOKIE: &str = "refresh_token";
+
+/// Gets the token from `Authorization: Bearer <token>`, an empty one
+/// is ignored since browsers are authenticated with cookies instead.
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .filter(|v| !v.is_empty())
+}
@@ -0,0 +1,96 @@
+use actix_web::FromRequest;
+
+use std::future::Future;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::pin::Pin;
+
+use crate::models::Permission;
+use crate::reqs::user::UserRestrictions;
+use crate::resp::error;
+
+/// Marker types for `Authorized`, one for each permission
+pub trait RequiredPermission {
+    const PERMISSION: Permission;
+}
+
+macro_rules! required_permissions {
+    ($($name:ident),* $(,)?) => {
+        $(
+            #[derive(Debug)]
+            pub struct $name;
+
+            impl RequiredPermission for $name {
+                const PERMISSION: Permission = Permission::$name;
+            }
+        )*
+    };
+}
+
+required_permissions!(
+    ReadSecretLetters,
+    ReadReports,
+    ResolveReports,
+    ManageUsers,
+    ToggleState,
+    ReadAuditLog,
+);
+
+/// Authenticated user having the permission `P`, otherwise
+/// the request is rejected before it reaches the handler.
+///
+/// ```ignore
+/// #[actix_web::delete("/api/reports/resolve/{id}")]
+/// pub async fn resolve(restrictions: Authorized<ResolveReports>) -> ... { }
+/// ```
+#[derive(Debug)]
+pub struct Authorized<P> {
+    restrictions: UserRestrictions,
+    _permission: PhantomData<P>,
+}
+
+impl<P> Authorized<P> {
+    pub fn into_inner(self) -> UserRestrictions {
+        self.restrictions
+    }
+}
+
+impl<P> Deref for Authorized<P> {
+    type Target = UserRestrictions;
+
+    fn deref(&self) -> &Self::Target {
+        &self.restrictions
+    }
+}
+
+impl<P: RequiredPermission> FromRequest for Authorized<P> {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let restrictions = UserRestrictions::from_request(req, payload);
+        Box::pin(async move {
+            let restrictions = restrictions.await?;
+            if !restrictions.has(P::PERMISSION) {
+                log::debug!(
+                    "[Authorized] {} is missing permission {}",
+                    restrictions.user_id,
+                    P::PERMISSION
+                );
+                return Err(error::ErrorForbidden(format!(
+                    "Not authorized to {}",
+                    P::PERMISSION.description()
+                ))
+                .into());
+            }
+
+            Ok(Self {
+                restrictions,
+                _permission: PhantomData,
+            })
+        })
+    }
+}
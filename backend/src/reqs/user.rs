@@ -9,16 +9,27 @@ use std::str::FromStr;
 
 use crate::config::AuthParams;
 use crate::db::{self, DbPool};
-use crate::models::UserToken;
+use crate::models::{Permission, UserToken};
+use crate::reqs::{bearer_token, TOKEN_COOKIE};
+use crate::resp::error::ApiError;
 
 /// Limitations of an individual user authenticated
 #[derive(Debug)]
 pub struct UserRestrictions {
-    pub moderator: bool,
-    pub viewer: bool,
+    pub user_id: Uuid,
+    pub session_id: Uuid,
+    /// Everything allowed by the roles of the user
+    pub permissions: Vec<Permission>,
 }
 
-static ACTIX_DATA_NOT_CONFIGURED: &str = "Requested application data is not configured correctly. \
+impl UserRestrictions {
+    pub fn has(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
+}
+
+pub(crate) static ACTIX_DATA_NOT_CONFIGURED: &str =
+    "Requested application data is not configured correctly. \
 View/enable debug logs for more details.";
 
 impl FromRequest for UserRestrictions {
@@ -42,18 +53,10 @@ impl FromRequest for UserRestrictions {
         };
 
         log::debug!("[restrictions] getting token");
-        let token = if let Some(auth) = req.headers().get("authorization") {
-            if let Ok(auth) = auth.to_str() {
-                if let Some(auth) = auth.strip_prefix("Bearer ") {
-                    log::debug!("[restrictions] using Authorization header");
-                    Some(auth.to_string())
-                } else {
-                    None
-                }
-            } else {
-                None
-            }
-        } else if let Some(cookie) = req.cookie("token") {
+        let token = if let Some(auth) = bearer_token(req.headers()) {
+            log::debug!("[restrictions] using Authorization header");
+            Some(auth.to_string())
+        } else if let Some(cookie) = req.cookie(TOKEN_COOKIE) {
             // maybe from the cookies?
             log::debug!("[restrictions] using from the cookie");
             Some(cookie.value().to_string())
@@ -62,13 +65,13 @@ impl FromRequest for UserRestrictions {
         };
 
         log::debug!("[restrictions] decoding token");
-        let auth = match token {
-            Some(token) => Some(UserToken::decode_token(&token, &params.token).map_err(|e| {
-                log::error!("[UserRestrictions] failed to decode token: {}", e);
-                error::ErrorInternalServerError("Failed to evaluate token")
-            })),
-            None => None,
-        };
+        let auth = token.map(|token| {
+            // expired tokens are expected, clients have to refresh them
+            UserToken::decode_token(&token, &params.token).map_err(|e| {
+                log::debug!("[UserRestrictions] failed to decode token: {}", e);
+                error::ErrorUnauthorized("Unauthorized")
+            })
+        });
 
         let token_data = match auth {
             Some(Ok(n)) => n,
@@ -84,7 +87,7 @@ impl FromRequest for UserRestrictions {
 
         // get the user from the sub field
         let pool = match req.app_data::<DbPool>() {
-            Some(n) => n,
+            Some(n) => n.clone(),
             None => {
                 log::debug!("[UserRestrictions] Failed to extract Data<DbPool>");
                 return Box::pin(async {
@@ -93,55 +96,41 @@ impl FromRequest for UserRestrictions {
             }
         };
 
-        let mut pool = match pool.get() {
-            Ok(n) => n,
-            Err(err) => {
-                log::error!("[UserRestrictions] db error: {}", err);
-                return Box::pin(async {
-                    Err(error::ErrorInternalServerError(
-                        error::ErrorInternalServerError("Failed to evaluate token"),
-                    ))
-                });
-            }
-        };
+        let ids = Uuid::from_str(&token_data.claims.sub)
+            .and_then(|user_id| Ok((user_id, Uuid::from_str(&token_data.claims.sid)?)));
 
-        let uuid = match Uuid::from_str(&token_data.claims.sub) {
+        let (user_id, session_id) = match ids {
             Ok(n) => n,
             Err(err) => {
-                log::error!("[UserRestrictions] invalid user id: {}", err);
-                return Box::pin(async {
-                    Err(error::ErrorInternalServerError(
-                        error::ErrorInternalServerError("Failed to evaluate token"),
-                    ))
-                });
-            }
-        };
-
-        let user = match db::users::find_by_id(&mut pool, &uuid) {
-            Ok(user) => user,
-            Err(err) => {
-                log::error!("[UserRestrictions] db error: {}", err);
+                log::error!("[UserRestrictions] invalid user or session id: {}", err);
                 return Box::pin(async {
-                    Err(error::ErrorInternalServerError(
-                        error::ErrorInternalServerError("Failed to evaluate token"),
-                    ))
+                    Err(error::ErrorInternalServerError("Failed to evaluate token"))
                 });
             }
         };
 
-        if let Some(user) = user {
-            Box::pin(async move {
-                Ok(UserRestrictions {
-                    moderator: user.moderator.unwrap_or_default(),
-                    viewer: user.viewer.unwrap_or_default(),
-                })
+        Box::pin(async move {
+            // revoked sessions don't have to wait for their tokens to expire
+            let now = chrono::Utc::now().naive_utc();
+            let permissions = db::run(&pool, move |conn| {
+                match db::sessions::find_active_user(conn, session_id, user_id, now)? {
+                    Some(_) => Ok(Some(db::roles::permissions_of_user(conn, user_id)?)),
+                    None => Ok(None),
+                }
             })
-        } else {
-            Box::pin(async {
-                Err(error::ErrorUnauthorized(json!({
+            .await
+            .map_err(ApiError::from)?;
+
+            match permissions {
+                Some(permissions) => Ok(UserRestrictions {
+                    user_id,
+                    session_id,
+                    permissions,
+                }),
+                None => Err(error::ErrorUnauthorized(json!({
                     "message": "Unauthorized",
-                })))
-            })
-        }
+                }))),
+            }
+        })
     }
 }
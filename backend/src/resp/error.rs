@@ -10,6 +10,8 @@ use std::fmt::Display;
 
 use serde_json::json;
 
+use crate::db::DbError;
+
 pub struct ApiError {
     status: StatusCode,
     message: String,
@@ -35,6 +37,23 @@ impl ApiError {
     }
 }
 
+impl From<DbError> for ApiError {
+    fn from(error: DbError) -> Self {
+        match error {
+            DbError::Unavailable(e) => {
+                log::error!("[ApiError] database is unavailable: {:#}", e);
+                ErrorServiceUnavailable(
+                    "Service is temporarily unavailable, please try again later",
+                )
+            }
+            DbError::Query(e) => {
+                log::error!("[ApiError] database error: {:#}", e);
+                Self::we_pretend_why_it_does_error()
+            }
+        }
+    }
+}
+
 impl<T> From<T> for ApiError
 where
     T: Into<anyhow::Error>,
@@ -46,7 +65,7 @@ where
 
 impl ResponseError for ApiError {
     fn status_code(&self) -> StatusCode {
-        self.status.clone()
+        self.status
     }
 
     fn error_response(&self) -> HttpResponse<BoxBody> {
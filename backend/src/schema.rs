@@ -1,5 +1,38 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    audit_log (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        actor_id -> Nullable<Uuid>,
+        action -> Varchar,
+        letter_id -> Nullable<Uuid>,
+        report_id -> Nullable<Uuid>,
+        user_id -> Nullable<Uuid>,
+        before -> Nullable<Jsonb>,
+        after -> Nullable<Jsonb>,
+    }
+}
+
+diesel::table! {
+    invite_roles (invite_id, role) {
+        invite_id -> Uuid,
+        role -> Varchar,
+    }
+}
+
+diesel::table! {
+    invites (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        created_by -> Nullable<Uuid>,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+        used_by -> Nullable<Uuid>,
+        code_hash -> Text,
+    }
+}
+
 diesel::table! {
     letters (id) {
         id -> Uuid,
@@ -7,6 +40,41 @@ diesel::table! {
         author -> Varchar,
         message -> Text,
         secret -> Bool,
+        status -> Varchar,
+        moderation_reason -> Nullable<Text>,
+        moderated_by -> Nullable<Uuid>,
+        moderated_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    login_failures (key) {
+        key -> Text,
+        failures -> Int4,
+        first_failed_at -> Timestamp,
+        last_failed_at -> Timestamp,
+        locked_until -> Nullable<Timestamp>,
+        lockouts -> Int4,
+    }
+}
+
+diesel::table! {
+    login_lockouts (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        key -> Text,
+        ip -> Nullable<Text>,
+        failures -> Int4,
+        locked_until -> Timestamp,
+    }
+}
+
+diesel::table! {
+    recovery_codes (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        code_hash -> Text,
+        used_at -> Nullable<Timestamp>,
     }
 }
 
@@ -15,11 +83,41 @@ diesel::table! {
         id -> Uuid,
         email -> Varchar,
         created_at -> Timestamp,
-        letter_id -> Uuid,
+        letter_id -> Nullable<Uuid>,
         #[sql_name = "type"]
         type_ -> Int4,
         details -> Text,
         resolved -> Bool,
+        resolved_at -> Nullable<Timestamp>,
+        resolved_by -> Nullable<Uuid>,
+    }
+}
+
+diesel::table! {
+    role_permissions (role, permission) {
+        role -> Varchar,
+        permission -> Varchar,
+    }
+}
+
+diesel::table! {
+    roles (name) {
+        name -> Varchar,
+        created_at -> Timestamp,
+        requires_2fa -> Bool,
+    }
+}
+
+diesel::table! {
+    sessions (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        created_at -> Timestamp,
+        refreshed_at -> Timestamp,
+        expires_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+        refresh_token -> Text,
+        previous_refresh_token -> Nullable<Text>,
     }
 }
 
@@ -27,6 +125,27 @@ diesel::table! {
     states (id) {
         id -> Int4,
         available -> Bool,
+        closes_at -> Nullable<Timestamp>,
+        closed_message -> Nullable<Text>,
+        opens_at -> Nullable<Timestamp>,
+        premoderation -> Bool,
+    }
+}
+
+diesel::table! {
+    user_roles (user_id, role) {
+        user_id -> Uuid,
+        role -> Varchar,
+    }
+}
+
+diesel::table! {
+    user_totp (user_id) {
+        user_id -> Uuid,
+        created_at -> Timestamp,
+        secret -> Text,
+        enabled_at -> Nullable<Timestamp>,
+        last_used_step -> Nullable<Int8>,
     }
 }
 
@@ -36,11 +155,35 @@ diesel::table! {
         created_at -> Timestamp,
         name -> Varchar,
         password -> Text,
-        moderator -> Nullable<Bool>,
-        viewer -> Nullable<Bool>,
     }
 }
 
+diesel::joinable!(invite_roles -> invites (invite_id));
+diesel::joinable!(invite_roles -> roles (role));
+diesel::joinable!(letters -> users (moderated_by));
+diesel::joinable!(recovery_codes -> users (user_id));
 diesel::joinable!(reports -> letters (letter_id));
+diesel::joinable!(reports -> users (resolved_by));
+diesel::joinable!(role_permissions -> roles (role));
+diesel::joinable!(sessions -> users (user_id));
+diesel::joinable!(user_roles -> roles (role));
+diesel::joinable!(user_roles -> users (user_id));
+diesel::joinable!(user_totp -> users (user_id));
 
-diesel::allow_tables_to_appear_in_same_query!(letters, reports, states, users,);
+diesel::allow_tables_to_appear_in_same_query!(
+    audit_log,
+    invite_roles,
+    invites,
+    letters,
+    login_failures,
+    login_lockouts,
+    recovery_codes,
+    reports,
+    role_permissions,
+    roles,
+    sessions,
+    states,
+    user_roles,
+    user_totp,
+    users,
+);
@@ -1,10 +1,12 @@
 use aes::cipher::generic_array::GenericArray;
 use aes::cipher::BlockDecrypt;
-use aes::cipher::BlockEncrypt;
 use aes::cipher::KeyInit;
 
 use aes::Aes128;
 
+use aes_gcm::aead::{Aead, AeadCore, OsRng, Payload};
+use aes_gcm::{Aes128Gcm, Nonce};
+
 use anyhow::anyhow;
 use anyhow::Result;
 
@@ -14,24 +16,30 @@ use flate2::{write::ZlibEncoder, Compression};
 use std::io::Write;
 use std::time::Instant;
 
+use crate::config::SecretKeys;
 use crate::vec_to_sized;
 
-fn defined_output(n: usize) -> String {
-    let n_str = n.to_string();
-    let n_len = n_str.len();
-    let gap = usize::MAX.to_string().len() - n_len;
-    let mut output = String::new();
-    for _ in 0..gap {
-        output.push('0');
-    }
-    output.push_str(&n_str);
-    output
-}
+// Legacy messages start with the zero padded length of the
+// compressed data, so they never collide with this prefix.
+//
+// AES-GCM messages are `v2$<key id>$<data>`
+static ENVELOPE_V2: &str = "v2$";
+
+static NONCE_LEN: usize = 12;
 
-pub async fn decrypt_message(key: &[u8; 16], author: &str, message: &str) -> Result<String> {
-    log::info!("[decrypt] decompressing message");
+/// Decrypts letters encrypted before authenticated encryption is used.
+///
+/// Each block was encrypted independently without any IV and the only
+/// integrity check is the author prefix, so it's only kept to read old rows.
+fn decrypt_legacy_message(key: &[u8; 16], author: &str, message: &str) -> Result<String> {
+    log::info!("[decrypt] decrypting legacy message");
 
-    let (bytes_len, message) = message.split_at(usize::MAX.to_string().len());
+    let header_len = usize::MAX.to_string().len();
+    if message.len() < header_len || !message.is_char_boundary(header_len) {
+        return Err(anyhow!("invalid legacy message"));
+    }
+
+    let (bytes_len, message) = message.split_at(header_len);
     let bytes_len = bytes_len.parse::<usize>().unwrap_or_default();
 
     let now = Instant::now();
@@ -41,7 +49,7 @@ pub async fn decrypt_message(key: &[u8; 16], author: &str, message: &str) -> Res
     log::info!("[decrypt] Base64 decoding done ({:.2?})", elapsed);
     log::info!("[decrypt] decrypting AES encrypted data");
     let now = Instant::now();
-    let key = GenericArray::from(key.clone());
+    let key = GenericArray::from(*key);
     let cipher = Aes128::new(&key);
 
     let mut bytes = Vec::new();
@@ -76,56 +84,259 @@ pub async fn decrypt_message(key: &[u8; 16], author: &str, message: &str) -> Res
     let raw_text = String::from_utf8_lossy(&decoded);
 
     // get rid of the prefix of the author thing
-    if !raw_text.starts_with(author) {
-        return Err(anyhow!("invalid header"));
-    }
-
-    Ok(raw_text.strip_prefix(author).unwrap().to_string())
+    strip_legacy_author(&raw_text, author)
+        .map(|v| v.to_string())
+        .ok_or_else(|| anyhow!("invalid header"))
 }
 
-pub async fn encrypt_message(key: &[u8; 16], author: &str, message: &str) -> Result<String> {
-    log::info!("[encrypt] compressing message");
+/// Legacy messages are prefixed with the author as it was typed but
+/// letters store it without any whitespace, so skip them while matching.
+fn strip_legacy_author<'a>(text: &'a str, author: &str) -> Option<&'a str> {
+    let mut expected = author.chars().peekable();
+    for (i, c) in text.char_indices() {
+        if expected.peek().is_none() {
+            return Some(&text[i..]);
+        }
+        if c.is_whitespace() {
+            continue;
+        }
+        if expected.next() != Some(c) {
+            return None;
+        }
+    }
 
-    let mut bytes = Vec::new();
-    let mut encoder = ZlibEncoder::new(bytes, Compression::fast());
+    expected.peek().is_none().then_some("")
+}
 
-    let now = Instant::now();
+fn compress(message: &str) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
     encoder
-        .write_all(format!("{}{}", author, message).as_bytes())
+        .write_all(message.as_bytes())
         .map_err(|e| anyhow!("zlib error: {}", e))?;
 
-    bytes = encoder.finish()?;
+    Ok(encoder.finish()?)
+}
 
-    let elapsed = now.elapsed();
-    log::info!("[encrypt] zlib compression done ({:.2?})", elapsed);
+fn decompress(bytes: &[u8]) -> Result<String> {
+    let mut decoder = ZlibDecoder::new(Vec::new());
+    decoder
+        .write_all(bytes)
+        .map_err(|e| anyhow!("zlib decompression error: {}", e))?;
 
-    let mut encrypted: Vec<u8> = Vec::new();
+    let decoded = decoder.finish()?;
+    String::from_utf8(decoded).map_err(|e| anyhow!("invalid UTF-8 message: {}", e))
+}
 
-    log::info!("[encrypt] encrypting zlib compressed data to AES");
+fn decrypt_aes_gcm(key: &[u8; 16], author: &str, envelope: &str) -> Result<String> {
     let now = Instant::now();
-    let key = GenericArray::from(key.clone());
-    let cipher = Aes128::new(&key);
+    let bytes = base64::decode(envelope).map_err(|e| anyhow!("decoding Base64 failed: {}", e))?;
+    if bytes.len() < NONCE_LEN {
+        return Err(anyhow!("encrypted message is too short"));
+    }
 
-    let bytes_len = defined_output(bytes.len());
-    let sections = crate::utils::slice::split_into_sections(&bytes, 16);
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    let cipher = Aes128Gcm::new(&GenericArray::from(*key));
+
+    // the author is authenticated along with the message so
+    // the message can't be moved to another letter
+    let compressed = cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: author.as_bytes(),
+            },
+        )
+        .map_err(|_| anyhow!("message authentication failed"))?;
+
+    let message = decompress(&compressed)?;
+    log::info!("[decrypt] AES-GCM decryption done ({:.2?})", now.elapsed());
+
+    Ok(message)
+}
 
-    for section in sections {
-        let mut sized = [0u8; 16];
-        vec_to_sized!(section, sized);
+/// Gets the id of the key used to encrypt the message, messages
+/// encrypted before key ids are introduced don't have one.
+pub fn key_id(message: &str) -> Option<&str> {
+    message
+        .strip_prefix(ENVELOPE_V2)
+        .and_then(|v| v.split_once('$'))
+        .map(|(id, _)| id)
+}
 
-        let mut block = GenericArray::from(sized);
-        cipher.encrypt_block(&mut block);
-        encrypted.extend(block.iter());
+/// Decrypts a secret letter, including the ones encrypted
+/// before AES-GCM is used.
+pub async fn decrypt_message(keys: &SecretKeys, author: &str, message: &str) -> Result<String> {
+    if let Some(envelope) = message.strip_prefix(ENVELOPE_V2) {
+        let (id, envelope) = envelope
+            .split_once('$')
+            .ok_or_else(|| anyhow!("missing key id"))?;
+
+        let key = keys
+            .find(id)
+            .ok_or_else(|| anyhow!("unknown secret key id {:?}", id))?;
+
+        return decrypt_aes_gcm(&key.key, author, envelope);
     }
 
-    let elapsed = now.elapsed();
-    log::info!("[encrypt] AES encryption done ({:.2?})", elapsed);
+    // we don't know which key is used for legacy messages
+    // so we have to try every key we have
+    let mut last_error = None;
+    for key in keys.iter() {
+        match decrypt_legacy_message(&key.key, author, message) {
+            Ok(message) => return Ok(message),
+            Err(err) => last_error = Some(err),
+        }
+    }
 
-    log::info!("[encrypt] converting AES data to Base64");
+    Err(last_error.unwrap_or_else(|| anyhow!("no secret keys available")))
+}
+
+pub async fn encrypt_message(keys: &SecretKeys, author: &str, message: &str) -> Result<String> {
     let now = Instant::now();
-    let value = base64::encode(encrypted);
-    let elapsed = now.elapsed();
+    let compressed = compress(message)?;
+
+    let cipher = Aes128Gcm::new(&GenericArray::from(keys.active.key));
+    let nonce = Aes128Gcm::generate_nonce(&mut OsRng);
+
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: &compressed,
+                aad: author.as_bytes(),
+            },
+        )
+        .map_err(|_| anyhow!("AES-GCM encryption failed"))?;
+
+    let mut bytes = nonce.to_vec();
+    bytes.extend(ciphertext);
+
+    log::info!("[encrypt] AES-GCM encryption done ({:.2?})", now.elapsed());
+    Ok(format!(
+        "{}{}${}",
+        ENVELOPE_V2,
+        keys.active.id,
+        base64::encode(bytes)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SecretKey;
+
+    static KEY_A: [u8; 16] = *b"0123456789abcdef";
+    static KEY_B: [u8; 16] = *b"fedcba9876543210";
+
+    // encrypted by the legacy `encrypt_message` with KEY_A, author "Mary Ann"
+    static LEGACY_MESSAGE: &str =
+        "00000000000000000029S2uZsnuRFXNBEH15NY1SgCGEZA/aNNHbVnZ9voEReNg=";
+
+    fn key(id: &str, key: [u8; 16]) -> SecretKey {
+        SecretKey {
+            id: id.to_string(),
+            key,
+        }
+    }
+
+    fn keys(active: SecretKey, retired: Vec<SecretKey>) -> SecretKeys {
+        SecretKeys { active, retired }
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let keys = keys(key("a", KEY_A), vec![]);
+        let encrypted = encrypt_message(&keys, "author", "hello there")
+            .await
+            .unwrap();
+
+        assert!(encrypted.starts_with("v2$a$"));
+        assert_eq!(key_id(&encrypted), Some("a"));
+        assert_eq!(
+            decrypt_message(&keys, "author", &encrypted).await.unwrap(),
+            "hello there"
+        );
+    }
+
+    #[tokio::test]
+    async fn same_message_encrypts_differently() {
+        let keys = keys(key("a", KEY_A), vec![]);
+        let first = encrypt_message(&keys, "author", "hello").await.unwrap();
+        let second = encrypt_message(&keys, "author", "hello").await.unwrap();
 
-    log::info!("[encrypt] Base64 conversion done ({:.2?})", elapsed);
-    Ok(format!("{}{}", bytes_len, value))
+        assert_ne!(first, second);
+    }
+
+    #[tokio::test]
+    async fn rejects_other_author() {
+        let keys = keys(key("a", KEY_A), vec![]);
+        let encrypted = encrypt_message(&keys, "author", "hello").await.unwrap();
+
+        assert!(decrypt_message(&keys, "someone", &encrypted).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_tampered_message() {
+        let keys = keys(key("a", KEY_A), vec![]);
+        let encrypted = encrypt_message(&keys, "author", "hello").await.unwrap();
+
+        let mut bytes = base64::decode(encrypted.strip_prefix("v2$a$").unwrap()).unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        let tampered = format!("v2$a${}", base64::encode(bytes));
+
+        assert!(decrypt_message(&keys, "author", &tampered).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn decrypts_legacy_message() {
+        let keys = keys(key("a", KEY_A), vec![]);
+
+        assert_eq!(key_id(LEGACY_MESSAGE), None);
+        assert_eq!(
+            decrypt_message(&keys, "MaryAnn", LEGACY_MESSAGE)
+                .await
+                .unwrap(),
+            "legacy letter"
+        );
+        // the author prefix is the only check legacy messages have
+        assert!(decrypt_message(&keys, "John", LEGACY_MESSAGE)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn decrypts_with_retired_key_id() {
+        let old = keys(key("a", KEY_A), vec![]);
+        let encrypted = encrypt_message(&old, "author", "hello").await.unwrap();
+
+        let rotated = keys(key("b", KEY_B), vec![key("a", KEY_A)]);
+        assert_eq!(
+            decrypt_message(&rotated, "author", &encrypted)
+                .await
+                .unwrap(),
+            "hello"
+        );
+
+        let forgotten = keys(key("b", KEY_B), vec![]);
+        assert!(decrypt_message(&forgotten, "author", &encrypted)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn tries_every_key_without_key_id() {
+        let rotated = keys(key("b", KEY_B), vec![key("a", KEY_A)]);
+        assert_eq!(
+            decrypt_message(&rotated, "MaryAnn", LEGACY_MESSAGE)
+                .await
+                .unwrap(),
+            "legacy letter"
+        );
+
+        let forgotten = keys(key("b", KEY_B), vec![]);
+        assert!(decrypt_message(&forgotten, "MaryAnn", LEGACY_MESSAGE)
+            .await
+            .is_err());
+    }
 }
@@ -1,2 +1,5 @@
 pub mod letter;
+pub mod password;
 pub mod slice;
+pub mod token;
+pub mod totp;
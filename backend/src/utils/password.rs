@@ -0,0 +1,133 @@
+use anyhow::{anyhow, Result};
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+use std::sync::OnceLock;
+
+use crate::config::PasswordConfig;
+
+/// Whether the password matches, and if so whether its
+/// hash should be replaced with the current settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verified {
+    Invalid,
+    Valid,
+    NeedsRehash,
+}
+
+impl Verified {
+    pub fn is_valid(self) -> bool {
+        self != Self::Invalid
+    }
+}
+
+fn argon2(config: &PasswordConfig) -> Result<Argon2<'static>> {
+    let params = Params::new(
+        config.memory_kib,
+        config.iterations,
+        config.parallelism,
+        None,
+    )
+    .map_err(|e| anyhow!("invalid password hashing cost: {}", e))?;
+
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
+
+/// Hashes the password with Argon2id and a random salt for each user.
+///
+/// It's slow on purpose, so call it in `web::block`.
+pub fn hash_password(config: &PasswordConfig, password: &str) -> Result<String> {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+
+    let salt = SaltString::b64_encode(&salt).map_err(|e| anyhow!("invalid salt: {}", e))?;
+    let hash = argon2(config)?
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow!("failed to hash password: {}", e))?;
+
+    Ok(hash.to_string())
+}
+
+/// Legacy hashes include the username, so they break when the user is renamed
+pub fn is_legacy_hash(hash: &str) -> bool {
+    hash.starts_with("$2")
+}
+
+/// Passwords used to be hashed with bcrypt along with the username
+/// and the global `SALT_KEY` as the salt of every user.
+fn verify_legacy_password(salt: &[u8; 16], username: &str, password: &str, hash: &str) -> bool {
+    let formed = format!("{}{:?}{}", username, salt, password);
+    bcrypt::verify(formed, hash).unwrap_or_default()
+}
+
+/// Verifies the password against both Argon2 and legacy bcrypt hashes.
+///
+/// It's slow on purpose, so call it in `web::block`.
+pub fn verify_password(
+    config: &PasswordConfig,
+    legacy_salt: &[u8; 16],
+    username: &str,
+    password: &str,
+    hash: &str,
+) -> Verified {
+    let parsed = match PasswordHash::new(hash) {
+        Ok(n) => n,
+        Err(_) if is_legacy_hash(hash) => {
+            return match verify_legacy_password(legacy_salt, username, password, hash) {
+                true => Verified::NeedsRehash,
+                false => Verified::Invalid,
+            };
+        }
+        Err(e) => {
+            log::error!("[verify_password] invalid password hash: {}", e);
+            return Verified::Invalid;
+        }
+    };
+
+    // the parameters are taken from the hash itself
+    if Argon2::default()
+        .verify_password(password.as_bytes(), &parsed)
+        .is_err()
+    {
+        return Verified::Invalid;
+    }
+
+    let outdated = parsed.algorithm != Algorithm::Argon2id.ident()
+        || Params::try_from(&parsed)
+            .map(|params| {
+                params.m_cost() != config.memory_kib
+                    || params.t_cost() != config.iterations
+                    || params.p_cost() != config.parallelism
+            })
+            .unwrap_or(true);
+
+    if outdated {
+        Verified::NeedsRehash
+    } else {
+        Verified::Valid
+    }
+}
+
+// hashed with the configured cost the first time it's needed
+static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+/// Verifies the password against a dummy hash for users who don't exist,
+/// so it takes as long as a wrong password and usernames can't be timed.
+///
+/// It's slow on purpose, so call it in `web::block`.
+pub fn verify_missing_user_password(config: &PasswordConfig, password: &str) {
+    let hash = DUMMY_HASH.get_or_init(|| {
+        hash_password(config, "not the password of anyone").unwrap_or_else(|e| {
+            log::error!("[verify_missing_user_password] failed to hash: {}", e);
+            String::new()
+        })
+    });
+
+    if let Ok(parsed) = PasswordHash::new(hash) {
+        let _ = Argon2::default().verify_password(password.as_bytes(), &parsed);
+    }
+}
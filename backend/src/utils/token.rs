@@ -0,0 +1,20 @@
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+/// Generates an opaque token (refresh tokens and invite codes),
+/// only its hash is stored in the database.
+pub fn generate() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+/// Only for random tokens of at least 128 bits (refresh tokens, invite
+/// and recovery codes), which are too many to brute force unlike
+/// passwords, so a plain hash is sufficient.
+pub fn hash(token: &str) -> String {
+    base64::encode_config(
+        openssl::sha::sha256(token.as_bytes()),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
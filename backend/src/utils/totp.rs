@@ -0,0 +1,264 @@
+use anyhow::{anyhow, Result};
+
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+use base32::Alphabet;
+
+/// Settings every authenticator app supports (RFC 6238 defaults)
+pub static TOTP_STEP: i64 = 30;
+pub static TOTP_DIGITS: u32 = 6;
+
+/// Codes from the previous and next step are accepted as
+/// well since clocks of the phone and server may drift.
+static ALLOWED_SKEW: i64 = 1;
+
+static SECRET_LEN: usize = 20;
+static RECOVERY_CODE_COUNT: usize = 10;
+// 160 bits, exactly 32 characters in base32
+const RECOVERY_CODE_LEN: usize = 20;
+
+const ALPHABET: Alphabet = Alphabet::RFC4648 { padding: false };
+
+/// Generates a random secret encoded in base32 as authenticator apps expect
+pub fn generate_secret() -> String {
+    let mut secret = vec![0u8; SECRET_LEN];
+    OsRng.fill_bytes(&mut secret);
+    base32::encode(ALPHABET, &secret)
+}
+
+fn code_at(secret: &[u8], step: i64) -> Result<String> {
+    let key = PKey::hmac(secret)?;
+    let mut signer = Signer::new(MessageDigest::sha1(), &key)?;
+    signer.update(&step.to_be_bytes())?;
+    let hmac = signer.sign_to_vec()?;
+
+    // dynamic truncation from RFC 4226
+    let offset = (hmac[hmac.len() - 1] & 0xf) as usize;
+    let value = u32::from_be_bytes([
+        hmac[offset] & 0x7f,
+        hmac[offset + 1],
+        hmac[offset + 2],
+        hmac[offset + 3],
+    ]);
+
+    Ok(format!(
+        "{:0width$}",
+        value % 10u32.pow(TOTP_DIGITS),
+        width = TOTP_DIGITS as usize
+    ))
+}
+
+/// Checks the code at `now` (in UNIX seconds), returning its time step if it
+/// matches. Steps up to `last_used_step` are rejected so codes can't be reused.
+pub fn verify(
+    secret: &str,
+    code: &str,
+    now: i64,
+    last_used_step: Option<i64>,
+) -> Result<Option<i64>> {
+    let secret = base32::decode(ALPHABET, secret).ok_or_else(|| anyhow!("invalid TOTP secret"))?;
+    let code = code.trim();
+    if code.len() != TOTP_DIGITS as usize || !code.bytes().all(|v| v.is_ascii_digit()) {
+        return Ok(None);
+    }
+
+    let current = now / TOTP_STEP;
+    for step in (current - ALLOWED_SKEW)..=(current + ALLOWED_SKEW) {
+        if last_used_step.map(|v| step <= v).unwrap_or_default() {
+            continue;
+        }
+        if memcmp::eq(code_at(&secret, step)?.as_bytes(), code.as_bytes()) {
+            return Ok(Some(step));
+        }
+    }
+
+    Ok(None)
+}
+
+fn encode_uri_component(value: &str) -> String {
+    value
+        .bytes()
+        .map(|v| match v {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (v as char).to_string()
+            }
+            _ => format!("%{:02X}", v),
+        })
+        .collect()
+}
+
+/// `otpauth://` URI shown as a QR code for authenticator apps to scan
+pub fn provisioning_uri(issuer: &str, account: &str, secret: &str) -> String {
+    let issuer = encode_uri_component(issuer);
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        issuer,
+        encode_uri_component(account),
+        secret,
+        issuer,
+        TOTP_DIGITS,
+        TOTP_STEP
+    )
+}
+
+/// Single use codes to log in without the authenticator app, formatted
+/// as `xxxxxxxx-xxxxxxxx-xxxxxxxx-xxxxxxxx` so they're easier to write down.
+///
+/// They're stored with a plain hash like other tokens, so they need
+/// as many random bits to not be brute forced from a leaked database.
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; RECOVERY_CODE_LEN];
+            OsRng.fill_bytes(&mut bytes);
+
+            base32::encode(ALPHABET, &bytes)
+                .to_ascii_lowercase()
+                .as_bytes()
+                .chunks(8)
+                .map(|v| String::from_utf8_lossy(v).into_owned())
+                .collect::<Vec<_>>()
+                .join("-")
+        })
+        .collect()
+}
+
+/// Recovery codes are compared without the dash and case
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.trim()
+        .chars()
+        .filter(|v| *v != '-')
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "12345678901234567890", the secret of the RFC 4226 and 6238 test vectors
+    static RFC_SECRET: &[u8] = b"12345678901234567890";
+    static RFC_SECRET_BASE32: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn rfc4226_vectors() {
+        let expected = [
+            "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583",
+            "399871", "520489",
+        ];
+        for (counter, code) in expected.iter().enumerate() {
+            assert_eq!(code_at(RFC_SECRET, counter as i64).unwrap(), *code);
+        }
+    }
+
+    #[test]
+    fn rfc6238_vectors() {
+        // the last 6 of the 8 digits in the RFC's SHA-1 table
+        let expected = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ];
+        for (time, code) in expected {
+            assert_eq!(
+                verify(RFC_SECRET_BASE32, code, time, None).unwrap(),
+                Some(time / TOTP_STEP)
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_one_step_of_skew() {
+        let now = 1234567890;
+        let current = now / TOTP_STEP;
+
+        for step in [current - 1, current, current + 1] {
+            let code = code_at(RFC_SECRET, step).unwrap();
+            assert_eq!(
+                verify(RFC_SECRET_BASE32, &code, now, None).unwrap(),
+                Some(step)
+            );
+        }
+        for step in [current - 2, current + 2] {
+            let code = code_at(RFC_SECRET, step).unwrap();
+            assert_eq!(verify(RFC_SECRET_BASE32, &code, now, None).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn rejects_used_steps() {
+        let now = 1234567890;
+        let current = now / TOTP_STEP;
+        let code = code_at(RFC_SECRET, current).unwrap();
+
+        assert_eq!(
+            verify(RFC_SECRET_BASE32, &code, now, Some(current)).unwrap(),
+            None
+        );
+        assert_eq!(
+            verify(RFC_SECRET_BASE32, &code, now, Some(current + 1)).unwrap(),
+            None
+        );
+        assert_eq!(
+            verify(RFC_SECRET_BASE32, &code, now, Some(current - 1)).unwrap(),
+            Some(current)
+        );
+
+        // an older code can't be used after a newer one
+        let previous = code_at(RFC_SECRET, current - 1).unwrap();
+        assert_eq!(
+            verify(RFC_SECRET_BASE32, &previous, now, Some(current)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_codes() {
+        let now = 1234567890;
+        for code in ["", "12345", "1234567", "12345a", "abcdef"] {
+            assert_eq!(verify(RFC_SECRET_BASE32, code, now, None).unwrap(), None);
+        }
+        assert!(verify("not base32!", "123456", now, None).is_err());
+    }
+
+    #[test]
+    fn generated_secret_is_usable() {
+        let secret = generate_secret();
+        let decoded = base32::decode(ALPHABET, &secret).unwrap();
+        assert_eq!(decoded.len(), SECRET_LEN);
+    }
+
+    #[test]
+    fn recovery_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+
+        for code in codes.iter() {
+            let groups = code.split('-').collect::<Vec<_>>();
+            assert_eq!(groups.len(), 4);
+            assert!(groups.iter().all(|v| v.len() == 8));
+
+            let normalized = normalize_recovery_code(code);
+            let decoded = base32::decode(ALPHABET, &normalized.to_ascii_uppercase()).unwrap();
+            assert_eq!(decoded.len(), RECOVERY_CODE_LEN);
+            assert_eq!(
+                normalize_recovery_code(&format!(" {} ", code.to_ascii_uppercase())),
+                normalized
+            );
+        }
+
+        let mut unique = codes.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), codes.len());
+    }
+}
@@ -16,7 +16,7 @@ import axios from "axios";
 import React, { useEffect, useState } from "react";
 import { DateTime } from "luxon";
 import { useMediaQuery } from "react-responsive";
-import { useCookies } from "react-cookie";
+import { hasPermission } from "../../utils/permissions";
 
 type DataType = {
   created_at?: string;
@@ -117,13 +117,12 @@ export default function DashboardPage() {
   const [list, setList] = useState<DataType[]>([]);
   const [offset, setOffset] = useState(0);
   const [empty, setEmpty] = useState(false);
-  const [cookies] = useCookies(["token"]);
   const [isReportAbuseOpen, setReportAbuse] = useState(false);
 
   const isMobile = useMediaQuery({ query: `(max-width: 760px)` });
   let apiUrl = "api/letters";
 
-  if (localStorage.getItem("viewer")) {
+  if (hasPermission("letters:read_secret")) {
     apiUrl = "api/letters/all";
   }
 
@@ -132,10 +131,8 @@ export default function DashboardPage() {
     const c = axios.CancelToken.source();
     axios
       .get(apiUrl, {
+        // the session cookie is HttpOnly, so it's sent by the browser
         withCredentials: true,
-        headers: {
-          authorization: cookies.token ? `Bearer ${cookies.token}` : "",
-        },
         cancelToken: c.token,
       })
       .then(res => {
@@ -177,9 +174,6 @@ export default function DashboardPage() {
     axios
       .get(`${apiUrl}?offset=${offset}`, {
         withCredentials: true,
-        headers: {
-          authorization: cookies.token ? `Bearer ${cookies.token}` : "",
-        },
         cancelToken: c.token,
       })
       .then(res => {
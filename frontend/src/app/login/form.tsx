@@ -20,6 +20,13 @@ interface Props {
   // eslint-disable-next-line no-unused-vars
   onPasswordChanged: (text: string) => void;
 
+  /** Set once the password is accepted and a TOTP code is needed */
+  twoFactor?: boolean;
+  code?: string;
+
+  // eslint-disable-next-line no-unused-vars
+  onCodeChanged?: (text: string) => void;
+
   onSubmit: () => void;
   disabled: boolean;
 }
@@ -31,29 +38,46 @@ export default function LoginForm(props: Props) {
         <Typography.Title style={{ textAlign: "center" }} level={2}>
           Login
         </Typography.Title>
-        <Input
-          disabled={props.disabled}
-          status={props.error?.username ? "error" : undefined}
-          id="username"
-          placeholder="Username"
-          onChange={e => {
-            e.preventDefault();
-            props.onUsernameChanged(e.target.value);
-          }}
-          value={props.username}
-        />
-        <Input
-          disabled={props.disabled}
-          id="password"
-          status={props.error?.password ? "error" : undefined}
-          placeholder="Password"
-          type="password"
-          onChange={e => {
-            e.preventDefault();
-            props.onPasswordChanged(e.target.value);
-          }}
-          value={props.password}
-        />
+        {props.twoFactor ? (
+          <Input
+            disabled={props.disabled}
+            id="code"
+            status={props.error ? "error" : undefined}
+            placeholder="Authenticator or recovery code"
+            autoComplete="one-time-code"
+            onChange={e => {
+              e.preventDefault();
+              props.onCodeChanged?.(e.target.value);
+            }}
+            value={props.code}
+          />
+        ) : (
+          <>
+            <Input
+              disabled={props.disabled}
+              status={props.error?.username ? "error" : undefined}
+              id="username"
+              placeholder="Username"
+              onChange={e => {
+                e.preventDefault();
+                props.onUsernameChanged(e.target.value);
+              }}
+              value={props.username}
+            />
+            <Input
+              disabled={props.disabled}
+              id="password"
+              status={props.error?.password ? "error" : undefined}
+              placeholder="Password"
+              type="password"
+              onChange={e => {
+                e.preventDefault();
+                props.onPasswordChanged(e.target.value);
+              }}
+              value={props.password}
+            />
+          </>
+        )}
         <br />
         <Button
           disabled={props.disabled}
@@ -64,7 +88,7 @@ export default function LoginForm(props: Props) {
             props.onSubmit();
           }}
         >
-          Login
+          {props.twoFactor ? "Verify" : "Login"}
         </Button>
         {props.error?.why && <Typography>{props.error.why}</Typography>}
       </Card>
@@ -1,6 +1,7 @@
-import axios from "axios";
+import axios, { AxiosResponse } from "axios";
 import React, { useReducer, useState } from "react";
 import { useNavigate } from "react-router";
+import { hasPermission, setPermissions } from "../../utils/permissions";
 import { tripleCase } from "../../utils/tripleCase";
 import LoginForm, { LoginFormError } from "./form";
 
@@ -62,21 +63,82 @@ const reducer = (
 export default function LoginPage() {
   const [username, setUsername] = useState("");
   const [password, setPassword] = useState("");
+  const [code, setCode] = useState("");
+  const [challenge, setChallenge] = useState<string>();
   const [disabled, setDisabled] = useState(false);
   const [diagnostic, dispatchDiagnostic] = useReducer(reducer, undefined);
 
   const navigate = useNavigate();
 
+  const instance = axios.create({
+    withCredentials: true,
+  });
+
+  const onResponse = async (response: AxiosResponse) => {
+    if (response.status === 202) {
+      setPermissions(response.data.permissions ?? []);
+      if (hasPermission("reports:read")) {
+        navigate("/reports");
+      } else {
+        navigate("/dashboard");
+      }
+      return;
+    }
+    if (response.data?.two_factor_required) {
+      setChallenge(response.data.challenge);
+      setDisabled(false);
+      return;
+    }
+    let message = "Something wrong with the server, please try again later";
+    if (response.headers?.authorization.endsWith("content-type")) {
+      message = response.data.message;
+    } else if (response.status === 429) {
+      message = "You're being ratelimited";
+    }
+    dispatchDiagnostic({
+      type: "API_ERROR",
+      message,
+    });
+  };
+
   return (
     <LoginForm
       username={username}
       password={password}
+      code={code}
+      twoFactor={challenge !== undefined}
       disabled={disabled}
       error={diagnostic}
       onUsernameChanged={setUsername}
       onPasswordChanged={setPassword}
+      onCodeChanged={setCode}
       onSubmit={() => {
         setDisabled(true);
+        if (challenge !== undefined) {
+          instance
+            .post("/api/users/login/totp", { challenge, code })
+            .then(onResponse)
+            .catch(reason => {
+              const message = reason.response?.data?.message;
+              if (!message) {
+                console.error(reason);
+                dispatchDiagnostic({ type: "UNEXPECTED_ERROR" });
+              } else {
+                dispatchDiagnostic({ type: "API_ERROR", message });
+              }
+              // the challenge only lasts a few minutes
+              if (
+                reason.response?.status === 401 &&
+                message !== "Invalid code"
+              ) {
+                setChallenge(undefined);
+                setCode("");
+              }
+              setDisabled(false);
+            });
+          return;
+        }
+
         dispatchDiagnostic({
           type: "AUTHENTICATE",
           username,
@@ -87,38 +149,12 @@ export default function LoginPage() {
         const noPassword = !password;
         if (noUsername || noPassword) return setDisabled(false);
 
-        const instance = axios.create({
-          withCredentials: true,
-        });
-
         instance
           .post("/api/users/login", {
             username,
             password,
           })
-          .then(async response => {
-            if (response.status === 202) {
-              localStorage.setItem("moderator", response.data.moderator);
-              localStorage.setItem("viewer", response.data.viewer);
-              if (response.data.moderator) {
-                navigate("/reports");
-              } else {
-                navigate("/dashboard");
-              }
-              return;
-            }
-            let message =
-              "Something wrong with the server, please try again later";
-            if (response.headers?.authorization.endsWith("content-type")) {
-              message = response.data.message;
-            } else if (response.status === 429) {
-              message = "You're being ratelimited";
-            }
-            dispatchDiagnostic({
-              type: "API_ERROR",
-              message,
-            });
-          })
+          .then(onResponse)
           .catch(reason => {
             console.error(reason);
             dispatchDiagnostic({ type: "UNEXPECTED_ERROR" });
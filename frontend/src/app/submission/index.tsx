@@ -5,6 +5,7 @@ import { Card, Divider, Typography } from "antd";
 import OSSPromotion from "../promotion";
 import axios from "axios";
 import UnavailableSubmissions from "./unavailable";
+import { Availability } from "../types";
 
 function SubmissionPublished() {
   return (
@@ -30,14 +31,16 @@ function SubmissionPublished() {
 
 export default function SubmissionPage() {
   const [submitted, setSubmitted] = useState(false);
-  const [available, setAvailable] = useState<null | boolean>(null);
+  const [availability, setAvailability] = useState<null | Availability>(
+    null,
+  );
 
   useEffect(() => {
     console.log("[DashboardPage] checking if submissions are available");
     const c = axios.CancelToken.source();
     axios
       .get("api/available", { cancelToken: c.token })
-      .then(res => setAvailable(res.data.available))
+      .then(res => setAvailability(res.data))
       .catch(err => {
         if (axios.isCancel(err)) {
           console.log("cancelled");
@@ -51,11 +54,11 @@ export default function SubmissionPage() {
     <>
       {submitted ? (
         <SubmissionPublished />
-      ) : available !== null ? (
-        available ? (
+      ) : availability !== null ? (
+        availability.available ? (
           <SubmissionPart onSubmitted={() => setSubmitted(true)} />
         ) : (
-          <UnavailableSubmissions />
+          <UnavailableSubmissions availability={availability} />
         )
       ) : (
         <Typography>
@@ -1,23 +1,53 @@
-import { Typography, Card } from "antd";
+import { Typography, Card, Statistic } from "antd";
 import React from "react";
+import { Availability } from "../types";
 
 const { Title } = Typography;
 
-export default function UnavailableSubmissions() {
+function UpcomingSubmissions(props: { opensIn: number }) {
+  // the server gives us seconds left, so we don't have to deal with
+  // timezones of the client and the server
+  const deadline = Date.now() + props.opensIn * 1000;
+  return (
+    <>
+      <Typography>
+        We're not accepting submissions yet. Come back on{" "}
+        <b>{new Date(deadline).toLocaleString()}</b>!
+      </Typography>
+      <Statistic.Countdown
+        style={{ textAlign: "center" }}
+        value={deadline}
+        format="D [days] HH:mm:ss"
+        onFinish={() => window.location.reload()}
+      />
+    </>
+  );
+}
+
+export default function UnavailableSubmissions(props: {
+  availability: Availability;
+}) {
+  const { message, opens_in } = props.availability;
   return (
     <div className="Center">
       <Card className="Center" style={{ width: 500, height: 200 }}>
         <Title style={{ textAlign: "center" }} level={2}>
           Submit Letter
         </Title>
-        <Typography>
-          {
-            "We're sad to report that we're not accepting any new submissions anymore."
-          }
-          <br />
-          Thank you for visiting this site! I hope you will submit a letter in
-          time next time!
-        </Typography>
+        {opens_in !== null ? (
+          <UpcomingSubmissions opensIn={opens_in} />
+        ) : message ? (
+          <Typography>{message}</Typography>
+        ) : (
+          <Typography>
+            {
+              "We're sad to report that we're not accepting any new submissions anymore."
+            }
+            <br />
+            Thank you for visiting this site! I hope you will submit a letter in
+            time next time!
+          </Typography>
+        )}
       </Card>
     </div>
   );
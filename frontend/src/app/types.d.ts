@@ -1,7 +1,21 @@
+export type LetterStatus = "visible" | "hidden" | "removed" | "pending_review";
+
 export interface Letter {
   created_at: string;
   author: string;
   id: string;
   message: string;
   secret: boolean;
+  // only given to users who can read every letter
+  status?: LetterStatus;
+}
+
+export interface Availability {
+  available: boolean;
+  opens_at: null | string;
+  closes_at: null | string;
+  opens_in: null | number;
+  closes_in: null | number;
+  message: null | string;
+  premoderation: boolean;
 }
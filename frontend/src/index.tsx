@@ -2,6 +2,9 @@ import React from "react";
 import ReactDOM from "react-dom/client";
 import { CookiesProvider } from "react-cookie";
 import App from "./app";
+import { installRefreshInterceptor } from "./utils/refreshSession";
+
+installRefreshInterceptor();
 
 const root = ReactDOM.createRoot(
   document.getElementById("root") as HTMLElement,
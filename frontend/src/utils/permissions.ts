@@ -0,0 +1,15 @@
+const STORAGE_KEY = "permissions";
+
+/** Remembers what the logged in user can do, only used to pick which pages to show */
+export function setPermissions(permissions: string[]) {
+  localStorage.setItem(STORAGE_KEY, JSON.stringify(permissions));
+}
+
+export function hasPermission(permission: string): boolean {
+  try {
+    const permissions = JSON.parse(localStorage.getItem(STORAGE_KEY) ?? "[]");
+    return Array.isArray(permissions) && permissions.includes(permission);
+  } catch {
+    return false;
+  }
+}
@@ -0,0 +1,86 @@
+import axios, { AxiosError, AxiosRequestConfig } from "axios";
+
+type RetriableConfig = AxiosRequestConfig & { _retried?: boolean };
+
+// failing with 401 is their actual answer, refreshing won't change it
+const NOT_REFRESHED_PATHS = [
+  "/api/users/login",
+  "/api/users/login/totp",
+  "/api/users/refresh",
+  "/api/users/logout",
+];
+
+const LOCK_NAME = "session-refresh";
+const REFRESHED_AT_KEY = "sessionRefreshedAt";
+
+// another tab refreshing this recently already sent us a new access token
+const REFRESH_GRACE_MS = 10 * 1000;
+
+type LockManagerLike = {
+  request<T>(name: string, callback: () => Promise<T>): Promise<T>;
+};
+
+// requests failing at the same time should only refresh once
+let refreshing: Promise<unknown> | null = null;
+
+function isRefreshable(url: string | undefined) {
+  if (!url) {
+    return false;
+  }
+  const path = new URL(url, window.location.origin).pathname;
+  return !NOT_REFRESHED_PATHS.includes(path);
+}
+
+function refreshedRecently() {
+  const refreshedAt = Number(localStorage.getItem(REFRESHED_AT_KEY));
+  return Date.now() - refreshedAt < REFRESH_GRACE_MS;
+}
+
+async function refresh() {
+  // the old refresh token is already exchanged, sending
+  // it again would look like it's stolen and revoke the session
+  if (refreshedRecently()) {
+    return;
+  }
+  await axios.post("/api/users/refresh", undefined, { withCredentials: true });
+  localStorage.setItem(REFRESHED_AT_KEY, Date.now().toString());
+}
+
+/**
+ * Tabs share the refresh token cookie, so only one of them may refresh at
+ * a time. Browsers without the Web Locks API only get it within the tab.
+ */
+function refreshOnce() {
+  const locks = (navigator as Navigator & { locks?: LockManagerLike }).locks;
+  return locks ? locks.request(LOCK_NAME, refresh) : refresh();
+}
+
+/**
+ * Access tokens only last for a few minutes, so get a new one with the
+ * refresh token cookie whenever the API says it's expired and try again.
+ */
+export function installRefreshInterceptor() {
+  axios.interceptors.response.use(undefined, async (error: AxiosError) => {
+    const config = error.config as RetriableConfig | undefined;
+    if (
+      error.response?.status !== 401 ||
+      !config ||
+      config._retried ||
+      !isRefreshable(config.url)
+    ) {
+      throw error;
+    }
+
+    refreshing ??= refreshOnce().finally(() => (refreshing = null));
+
+    try {
+      await refreshing;
+    } catch {
+      throw error;
+    }
+
+    // the new session cookie is sent along automatically
+    config._retried = true;
+    return axios(config);
+  });
+}